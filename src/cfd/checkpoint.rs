@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::cfd::sph::simulation::{SimulationState, SPH};
+use crate::scene::world_map::{WorldMap, WorldState};
+
+// Dynamic state of a run. The static setup comes from the config, so a
+// checkpoint can be resumed with a modified config to fork the run.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    simulation: SimulationState,
+    world: WorldState,
+}
+
+impl Checkpoint {
+    pub fn capture(sph: &SPH, world_map: &WorldMap) -> Self {
+        Self {
+            simulation: sph.save_state(),
+            world: world_map.save_state(),
+        }
+    }
+
+    pub fn load(filename: &str) -> Self {
+        let file = std::fs::File::open(filename).expect("Could not open checkpoint");
+        serde_json::from_reader(std::io::BufReader::new(file)).expect("Could not read checkpoint")
+    }
+
+    pub fn save(&self, filename: &Path) {
+        let file = std::fs::File::create(filename).expect("Could not create checkpoint");
+        serde_json::to_writer(std::io::BufWriter::new(file), self)
+            .expect("Could not write checkpoint");
+    }
+
+    pub fn restore(self, sph: &mut SPH, world_map: &mut WorldMap) {
+        sph.restore_state(self.simulation);
+        world_map.restore_state(self.world, sph.get_time());
+        sph.rebuild_boundaries(world_map);
+    }
+}
+
+// Writes a checkpoint into `directory` every `interval` simulated seconds.
+pub struct Checkpointer {
+    directory: PathBuf,
+    interval: f32,
+    next: f32,
+}
+
+impl Checkpointer {
+    pub fn new(directory: &str, interval: f32, time: f32) -> Self {
+        std::fs::create_dir_all(directory).expect("Could not create checkpoint directory");
+
+        Self {
+            directory: PathBuf::from(directory),
+            interval,
+            next: ((time / interval).floor() + 1.0) * interval,
+        }
+    }
+
+    pub fn update(&mut self, sph: &SPH, world_map: &WorldMap) {
+        let time = sph.get_time();
+
+        if time < self.next {
+            return;
+        }
+
+        let filename = self.directory.join(format!("checkpoint-{:.3}.json", time));
+        Checkpoint::capture(sph, world_map).save(&filename);
+        log::info!("Wrote checkpoint {} at {}s", filename.display(), time);
+
+        while self.next <= time {
+            self.next += self.interval;
+        }
+    }
+}
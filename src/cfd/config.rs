@@ -1,11 +1,22 @@
 use glam::Vec3;
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use strum_macros::EnumString;
+use std::collections::{BTreeMap, HashMap};
+use strum_macros::{EnumIter, EnumString};
+
+pub const DEFAULT_MATERIAL: &str = "default";
 
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
 pub struct SimulationConfig {
+    pub step: f32,
+    #[serde(default)]
+    pub adaptive_step: Option<AdaptiveStepConfig>,
+    #[serde(default)]
+    pub integrator: IntegratorKind,
     pub radius: f32,
+    #[serde(default)]
+    pub kernels: KernelConfig,
     pub mass: f32,
     pub gas_constant: f32,
     pub rest_density: f32,
@@ -19,6 +30,313 @@ pub struct SimulationConfig {
     pub buoyancy_direction: Vec3,
     pub gravity: Vec3,
     pub virtual_particle: Vec3,
+    #[serde(default)]
+    pub ambient: AmbientConfig,
+    #[serde(default)]
+    pub liquid: LiquidConfig,
+    #[serde(default)]
+    pub phases: PhaseConfig,
+    #[serde(default)]
+    pub boundary: BoundaryConfig,
+    #[serde(default)]
+    pub airflow: AirflowConfig,
+    #[serde(default)]
+    pub budget: BudgetConfig,
+    #[serde(default)]
+    pub neighbor_search: NeighborSearch,
+    #[serde(default)]
+    pub threads: Option<usize>,
+}
+
+impl SimulationConfig {
+    pub fn default_material(&self) -> Material {
+        Material {
+            mass: self.mass,
+            rest_density: self.rest_density,
+            gas_constant: self.gas_constant,
+            viscosity: self.viscosity,
+            thermal_conductivity: self.thermal_conductivity,
+        }
+    }
+
+    pub fn ambient_density(&self) -> f32 {
+        self.ambient.density.unwrap_or(self.rest_density)
+    }
+}
+
+// Pressures in the solver are gauge pressures relative to the ambient air, so
+// the atmosphere is described by its temperature and density alone.
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[serde(default)]
+pub struct AmbientConfig {
+    pub temperature: f32,
+    pub density: Option<f32>,
+}
+
+impl Default for AmbientConfig {
+    fn default() -> Self {
+        Self {
+            temperature: 25.0,
+            density: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, EnumString, PartialEq, Clone, Copy, Default)]
+pub enum NeighborSearch {
+    #[default]
+    Grid,
+    BruteForce,
+}
+
+#[derive(Serialize, Deserialize, Debug, EnumString, PartialEq, Clone, Copy, Default)]
+pub enum BudgetPolicy {
+    #[default]
+    Throttle,
+    RecycleOldest,
+    Merge,
+}
+
+// Applies to the whole simulation, while actuators may set their own
+// `max_particles`. Either limit triggers the policy.
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Default)]
+#[serde(default)]
+pub struct BudgetConfig {
+    pub max_particles: Option<usize>,
+    pub policy: BudgetPolicy,
+}
+
+#[derive(Serialize, Deserialize, Debug, EnumString, PartialEq, Clone, Copy, Default)]
+pub enum IntegratorKind {
+    SymplecticEuler,
+    Leapfrog,
+    #[default]
+    VelocityVerlet,
+    PredictorCorrector,
+}
+
+#[derive(Serialize, Deserialize, Debug, EnumString, EnumIter, PartialEq, Clone, Copy)]
+pub enum KernelKind {
+    Poly6,
+    Spiky,
+    Viscosity,
+    CubicSpline,
+    WendlandC2,
+    WendlandC4,
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[serde(default)]
+pub struct KernelConfig {
+    pub density: KernelKind,
+    pub pressure: KernelKind,
+    pub viscosity: KernelKind,
+}
+
+impl Default for KernelConfig {
+    fn default() -> Self {
+        Self {
+            density: KernelKind::Poly6,
+            pressure: KernelKind::Spiky,
+            viscosity: KernelKind::Viscosity,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+pub struct AdaptiveStepConfig {
+    pub min_step: f32,
+    pub max_step: f32,
+    #[serde(default = "AdaptiveStepConfig::default_cfl")]
+    pub cfl: f32,
+    #[serde(default = "AdaptiveStepConfig::default_viscous")]
+    pub viscous: f32,
+    #[serde(default = "AdaptiveStepConfig::default_force")]
+    pub force: f32,
+}
+
+impl AdaptiveStepConfig {
+    pub fn default_cfl() -> f32 {
+        0.4
+    }
+
+    fn default_viscous() -> f32 {
+        0.125
+    }
+
+    fn default_force() -> f32 {
+        0.25
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, EnumString, PartialEq, Clone, Copy, Default)]
+pub enum EquationOfState {
+    #[default]
+    Linear,
+    Tait,
+}
+
+#[derive(Serialize, Deserialize, Debug, EnumString, PartialEq, Clone, Copy, Default)]
+pub enum PressureSolver {
+    #[default]
+    Explicit,
+    Pcisph,
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[serde(default)]
+pub struct LiquidConfig {
+    pub equation_of_state: EquationOfState,
+    pub speed_of_sound: f32,
+    pub exponent: f32,
+    pub rest_density: Option<f32>,
+    pub pressure_solver: PressureSolver,
+    pub tolerance: f32,
+    pub min_iterations: usize,
+    pub max_iterations: usize,
+    pub surface_tension: f32,
+    pub adhesion: f32,
+}
+
+impl Default for LiquidConfig {
+    fn default() -> Self {
+        Self {
+            equation_of_state: EquationOfState::Linear,
+            speed_of_sound: 10.0,
+            exponent: 7.0,
+            rest_density: None,
+            pressure_solver: PressureSolver::Explicit,
+            tolerance: 0.01,
+            min_iterations: 3,
+            max_iterations: 50,
+            surface_tension: 0.0,
+            adhesion: 0.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, EnumString, PartialEq, Clone, Copy, Default)]
+pub enum PhaseCoupling {
+    #[default]
+    Isolated,
+    Coupled,
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[serde(default)]
+pub struct PhaseConfig {
+    pub coupling: PhaseCoupling,
+    pub density_ratio: f32,
+    pub drag_coefficient: f32,
+}
+
+impl Default for PhaseConfig {
+    fn default() -> Self {
+        Self {
+            coupling: PhaseCoupling::Isolated,
+            density_ratio: 1000.0,
+            drag_coefficient: 1.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, EnumString, PartialEq, Clone, Copy, Default)]
+pub enum BoundaryHandling {
+    #[default]
+    Solid,
+    Delete,
+}
+
+#[derive(Serialize, Deserialize, Debug, EnumString, PartialEq, Clone, Copy, Default)]
+pub enum Slip {
+    #[default]
+    FreeSlip,
+    NoSlip,
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[serde(default)]
+pub struct BoundaryConfig {
+    pub handling: BoundaryHandling,
+    pub slip: Slip,
+    pub restitution: f32,
+    pub stiffness: f32,
+    pub friction: f32,
+    pub thermal: ThermalConfig,
+}
+
+impl Default for BoundaryConfig {
+    fn default() -> Self {
+        Self {
+            handling: BoundaryHandling::Solid,
+            slip: Slip::FreeSlip,
+            restitution: 0.5,
+            stiffness: 1000.0,
+            friction: 10.0,
+            thermal: ThermalConfig::default(),
+        }
+    }
+}
+
+// `+` tiles blow in at `inlet_velocity` and `-` tiles extract whatever the
+// inlets and labelled vents supply.
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[serde(default)]
+pub struct AirflowConfig {
+    pub inlet_velocity: f32,
+    pub drag: f32,
+    pub iterations: usize,
+    pub tolerance: f32,
+}
+
+impl Default for AirflowConfig {
+    fn default() -> Self {
+        Self {
+            inlet_velocity: 0.0,
+            drag: 1.0,
+            iterations: 5000,
+            tolerance: 1e-5,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+pub struct SurfaceThermalConfig {
+    pub temperature: f32,
+    pub heat_transfer: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[serde(default)]
+pub struct ThermalConfig {
+    pub wall: SurfaceThermalConfig,
+    pub floor: SurfaceThermalConfig,
+    pub ceiling: SurfaceThermalConfig,
+    pub heater: SurfaceThermalConfig,
+    pub window: SurfaceThermalConfig,
+}
+
+impl Default for ThermalConfig {
+    fn default() -> Self {
+        let adiabatic = SurfaceThermalConfig {
+            temperature: 25.0,
+            heat_transfer: 0.0,
+        };
+
+        Self {
+            wall: adiabatic,
+            floor: adiabatic,
+            ceiling: adiabatic,
+            heater: SurfaceThermalConfig {
+                temperature: 40.0,
+                heat_transfer: 1.0,
+            },
+            window: SurfaceThermalConfig {
+                temperature: 10.0,
+                heat_transfer: 1.0,
+            },
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -33,6 +351,42 @@ pub enum FluidType {
     Liquid,
 }
 
+// How much of the scalar payload and rendered size is left as a particle ages.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(tag = "curve")]
+pub enum Decay {
+    #[default]
+    Constant,
+    // Fades out over the particle lifetime.
+    Linear,
+    Exponential {
+        half_life: f32,
+    },
+}
+
+// Spread of the launch directions around `direction`, as the largest angle
+// from it in degrees.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(tag = "shape")]
+pub enum EmissionPattern {
+    #[default]
+    Jet,
+    Cone {
+        spread: f32,
+    },
+    // Spreads sideways in the horizontal plane through the direction.
+    Fan {
+        spread: f32,
+    },
+}
+
+// Emits during the first `duty` fraction of every `period` seconds.
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+pub struct PulseConfig {
+    pub period: f32,
+    pub duty: f32,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ActuatorConfig {
     pub height: f32,
@@ -43,6 +397,45 @@ pub struct ActuatorConfig {
     pub fluid_type: FluidType,
     pub interval: f32,
     pub particle: ParticleConfig,
+    #[serde(default)]
+    pub material: Option<String>,
+    #[serde(default)]
+    pub scalars: HashMap<String, f32>,
+    #[serde(default)]
+    pub lifetime: Option<f32>,
+    #[serde(default)]
+    pub decay: Decay,
+    #[serde(default)]
+    pub max_particles: Option<usize>,
+    #[serde(default)]
+    pub pattern: EmissionPattern,
+    #[serde(default = "ActuatorConfig::default_burst")]
+    pub burst: usize,
+    #[serde(default)]
+    pub velocity_jitter: f32,
+    #[serde(default)]
+    pub pulse: Option<PulseConfig>,
+    #[serde(default = "ActuatorConfig::default_enabled")]
+    pub enabled: bool,
+}
+
+impl ActuatorConfig {
+    fn default_burst() -> usize {
+        1
+    }
+
+    fn default_enabled() -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+pub struct Material {
+    pub mass: f32,
+    pub rest_density: f32,
+    pub gas_constant: f32,
+    pub viscosity: f32,
+    pub thermal_conductivity: f32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -52,21 +445,156 @@ pub struct SensorConfig {
     pub output: Option<String>,
 }
 
+// Removes particles inside a box of the given size centred on the tile, at most
+// `rate` particles per second when a rate is given.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SinkConfig {
+    pub height: f32,
+    pub range: Vec3,
+    #[serde(default)]
+    pub rate: Option<f32>,
+}
+
+// Opening in a wall with its own flow. Positive velocities blow into the room
+// and negative ones extract from it.
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+pub struct VentConfig {
+    pub velocity: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+pub struct DoorEvent {
+    pub time: f32,
+    pub open: bool,
+}
+
+// Doors are closed unless `open` is set and toggle at the scheduled times.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DoorConfig {
+    #[serde(default)]
+    pub open: bool,
+    #[serde(default)]
+    pub schedule: Vec<DoorEvent>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+pub struct Keyframe<T> {
+    pub time: f32,
+    pub value: T,
+}
+
+// Keyframes for the properties of one actuator, in simulated seconds. Before
+// its first keyframe a property keeps the value from the actuator section;
+// numbers are interpolated linearly between keyframes and `enabled` holds
+// until the next one.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct TimelineConfig {
+    pub enabled: Vec<Keyframe<bool>>,
+    pub interval: Vec<Keyframe<f32>>,
+    pub temperature: Vec<Keyframe<f32>>,
+    pub velocity: Vec<Keyframe<f32>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EnvironmentConfig {
+    pub map: String,
+    #[serde(default = "EnvironmentConfig::default_tile_size")]
+    pub tile_size: f32,
+    #[serde(default = "EnvironmentConfig::default_ceiling_height")]
+    pub ceiling_height: f32,
+}
+
+impl EnvironmentConfig {
+    fn default_tile_size() -> f32 {
+        1.0
+    }
+
+    fn default_ceiling_height() -> f32 {
+        3.0
+    }
+}
+
+// The environment may be given as just the map, using 1 m tiles and a 3 m
+// ceiling.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Environment {
+    Map(String),
+    Settings(EnvironmentConfig),
+}
+
+impl From<Environment> for EnvironmentConfig {
+    fn from(environment: Environment) -> Self {
+        match environment {
+            Environment::Map(map) => Self {
+                map,
+                tile_size: Self::default_tile_size(),
+                ceiling_height: Self::default_ceiling_height(),
+            },
+            Environment::Settings(settings) => settings,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+pub struct ScalarConfig {
+    pub diffusion: f32,
+    #[serde(default)]
+    pub decay: f32,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
-    environment: String,
+    #[serde(deserialize_with = "deserialize_environment")]
+    environment: EnvironmentConfig,
     actuators: HashMap<char, ActuatorConfig>,
     sensors: HashMap<char, SensorConfig>,
+    #[serde(default)]
+    sinks: HashMap<char, SinkConfig>,
+    #[serde(default)]
+    doors: HashMap<char, DoorConfig>,
+    #[serde(default)]
+    vents: HashMap<char, VentConfig>,
+    #[serde(default)]
+    timeline: HashMap<char, TimelineConfig>,
+    #[serde(default)]
+    materials: HashMap<String, Material>,
+    #[serde(default)]
+    scalars: BTreeMap<String, ScalarConfig>,
+    #[serde(default)]
+    seed: Option<u64>,
     simulation: SimulationConfig,
 }
 
+fn deserialize_environment<'de, D>(deserializer: D) -> Result<EnvironmentConfig, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Environment::deserialize(deserializer).map(EnvironmentConfig::from)
+}
+
 impl Config {
     pub fn new(filename: &String) -> Self {
         let file = std::fs::File::open(filename).expect("Could not open file");
-        serde_yaml::from_reader(file).expect("Could not read file")
+        let config: Self = serde_yaml::from_reader(file).expect("Could not read file");
+
+        // The PCISPH prediction moves particles with the pressure acceleration
+        // inside the step, which only symplectic Euler does.
+        let simulation = &config.simulation;
+        if simulation.liquid.pressure_solver == PressureSolver::Pcisph
+            && simulation.integrator != IntegratorKind::SymplecticEuler
+        {
+            panic!(
+                "PCISPH requires the SymplecticEuler integrator, got {:?}",
+                simulation.integrator
+            );
+        }
+
+        config
     }
 
-    pub fn get_environment(&self) -> &String {
+    pub fn get_environment(&self) -> &EnvironmentConfig {
         &self.environment
     }
 
@@ -78,7 +606,95 @@ impl Config {
         self.sensors.get(label)
     }
 
+    pub fn get_actuators(&self) -> &HashMap<char, ActuatorConfig> {
+        &self.actuators
+    }
+
+    pub fn get_sink_by_label(&self, label: &char) -> Option<&SinkConfig> {
+        self.sinks.get(label)
+    }
+
+    pub fn get_door_by_label(&self, label: &char) -> Option<&DoorConfig> {
+        self.doors.get(label)
+    }
+
+    pub fn get_vent_by_label(&self, label: &char) -> Option<&VentConfig> {
+        self.vents.get(label)
+    }
+
+    pub fn get_timeline(&self) -> &HashMap<char, TimelineConfig> {
+        &self.timeline
+    }
+
+    // Actuators without a material use the properties in the simulation section.
+    pub fn get_material(&self, name: Option<&String>) -> Material {
+        match name {
+            None => self.simulation.default_material(),
+            Some(name) => match self.materials.get(name) {
+                Some(material) => *material,
+                None => panic!("Unknown material {}", name),
+            },
+        }
+    }
+
+    pub fn get_material_name(&self, name: Option<&String>) -> String {
+        name.cloned()
+            .unwrap_or_else(|| DEFAULT_MATERIAL.to_string())
+    }
+
+    // Particles store scalars in the order of these names.
+    pub fn get_scalar_names(&self) -> Vec<String> {
+        self.scalars.keys().cloned().collect()
+    }
+
+    pub fn get_scalars(&self) -> Vec<ScalarConfig> {
+        self.scalars.values().copied().collect()
+    }
+
+    pub fn get_concentrations(&self, concentrations: &HashMap<String, f32>) -> Vec<f32> {
+        if let Some(name) = concentrations
+            .keys()
+            .find(|name| !self.scalars.contains_key(*name))
+        {
+            panic!("Unknown scalar {}", name);
+        }
+
+        self.scalars
+            .keys()
+            .map(|name| concentrations.get(name).copied().unwrap_or(0.0))
+            .collect()
+    }
+
     pub fn get_simulation_config(&self) -> &SimulationConfig {
         &self.simulation
     }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
+
+    // Runs without a seed get a random one, logged so the run can be repeated.
+    pub fn ensure_seed(&mut self) {
+        if self.seed.is_none() {
+            let seed = rand::random();
+            log::warn!(
+                "Using random seed {}, pass --seed {} to repeat this run",
+                seed,
+                seed
+            );
+            self.seed = Some(seed);
+        }
+    }
+
+    // Every actuator draws from its own stream of the seeded generator, so
+    // adding an actuator leaves the others untouched.
+    pub fn get_actuator_rng(&self, label: &char) -> ChaCha12Rng {
+        let mut rng = ChaCha12Rng::seed_from_u64(self.seed.unwrap_or_default());
+        rng.set_stream(*label as u64);
+        rng
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        self.simulation.threads = Some(threads);
+    }
 }
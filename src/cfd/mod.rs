@@ -1,2 +1,4 @@
+pub mod checkpoint;
 pub mod config;
+pub mod report;
 pub mod sph;
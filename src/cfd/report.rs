@@ -0,0 +1,82 @@
+use crate::cfd::sph::particle_id::ParticleId;
+use crate::cfd::sph::simulation::SPH;
+use crate::scene::world_map::WorldMap;
+
+// Logs the particles lost so far, how the budget changed the emissions, what
+// each sink extracted and where the tracked particles are every `interval`
+// simulated seconds.
+pub struct Reporter {
+    interval: f32,
+    next: f32,
+    tracked: Vec<ParticleId>,
+}
+
+impl Reporter {
+    pub fn new(interval: f32, time: f32) -> Self {
+        Self {
+            interval,
+            next: ((time / interval).floor() + 1.0) * interval,
+            tracked: Vec::new(),
+        }
+    }
+
+    pub fn with_tracked(mut self, tracked: Vec<ParticleId>) -> Self {
+        self.tracked = tracked;
+        self
+    }
+
+    pub fn update(&mut self, sph: &SPH, world_map: &WorldMap) {
+        let time = sph.get_time();
+
+        if time < self.next {
+            return;
+        }
+
+        let losses = sph.get_losses();
+        log::info!(
+            "Lost {} particles to the walls and {} to their lifetime by {}s",
+            losses.walls,
+            losses.expired,
+            time
+        );
+
+        let budget = sph.get_budget_stats();
+        log::info!(
+            "Particle budget dropped {}, recycled {} and merged {} particles by {}s",
+            budget.throttled,
+            budget.recycled,
+            budget.merged,
+            time
+        );
+
+        for (label, sink) in world_map.iter_sinks() {
+            if sink.get_extracted().is_empty() {
+                log::info!("Sink {} extracted nothing by {}s", label, time);
+            }
+
+            for (material, extraction) in sink.get_extracted() {
+                log::info!(
+                    "Sink {} extracted {} particles ({} kg) of {} by {}s",
+                    label,
+                    extraction.count,
+                    extraction.mass,
+                    material,
+                    time
+                );
+            }
+        }
+
+        for id in &self.tracked {
+            match sph.get_particle(*id) {
+                Some(particle) => {
+                    log::info!("Particle {} is at {} at {}s", id, particle.position, time)
+                }
+                None => log::info!("Particle {} is not simulated at {}s", id, time),
+            }
+        }
+
+        while self.next <= time {
+            self.next += self.interval;
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use glam::Vec3;
+use std::collections::HashMap;
+
+use crate::cfd::config::AirflowConfig;
+use crate::{Tile, WorldMap};
+
+// Vents carry their inflow velocity, outlets balance whatever the vents supply.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cell {
+    Solid,
+    Open,
+    Vent(f32),
+    Outlet,
+}
+
+const NEIGHBORS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+// Steady potential flow over the tile grid, driven by inlet, outlet and vent
+// tiles in the walls. The field is horizontal and stored at tile centres.
+pub struct Airflow {
+    velocities: HashMap<(i32, i32), Vec3>,
+    tile_size: f32,
+    drag: f32,
+}
+
+impl Airflow {
+    pub fn new(world_map: &WorldMap, config: AirflowConfig) -> Self {
+        let tile_size = world_map.get_tile_size();
+        let cells: HashMap<(i32, i32), Cell> = world_map
+            .iter_tiles()
+            .map(|(x, z, tile)| {
+                let cell = match tile {
+                    Tile::Empty | Tile::Wall | Tile::Window => Cell::Solid,
+                    Tile::Inlet => Cell::Vent(config.inlet_velocity),
+                    Tile::Outlet => Cell::Outlet,
+                    Tile::Vent(label) => match world_map.get_vent_by_label(label) {
+                        Some(vent) => Cell::Vent(vent.velocity),
+                        None => Cell::Solid,
+                    },
+                    _ => Cell::Open,
+                };
+
+                ((x as i32, z as i32), cell)
+            })
+            .collect();
+
+        let cell = |x: i32, z: i32| cells.get(&(x, z)).copied().unwrap_or(Cell::Solid);
+
+        let mut open: Vec<(i32, i32)> = cells
+            .iter()
+            .filter(|(_, cell)| **cell == Cell::Open)
+            .map(|(position, _)| *position)
+            .collect();
+        open.sort();
+
+        let faces: Vec<Cell> = open
+            .iter()
+            .flat_map(|(x, z)| NEIGHBORS.iter().map(move |(dx, dz)| cell(x + dx, z + dz)))
+            .collect();
+
+        let outlet_faces = faces.iter().filter(|cell| **cell == Cell::Outlet).count();
+        let (supply, driven) = faces
+            .iter()
+            .filter_map(|cell| match cell {
+                Cell::Vent(velocity) => Some(*velocity),
+                _ => None,
+            })
+            .fold((0.0f32, false), |(supply, driven), velocity| {
+                (supply + velocity, driven || velocity != 0.0)
+            });
+
+        let mut airflow = Self {
+            velocities: HashMap::new(),
+            tile_size,
+            drag: config.drag,
+        };
+
+        if !driven {
+            return airflow;
+        }
+
+        // The flow only has a steady state when as much air leaves as enters.
+        if outlet_faces == 0 && supply.abs() > f32::EPSILON {
+            log::warn!(
+                "Ignoring airflow: vents supply a net {} m/s over their faces and there is no outlet",
+                supply
+            );
+            return airflow;
+        }
+
+        // Outlets extract exactly what the vents supply.
+        let outlet = match outlet_faces {
+            0 => 0.0,
+            faces => supply / faces as f32,
+        };
+
+        let mut potential: HashMap<(i32, i32), f32> = open.iter().map(|c| (*c, 0.0)).collect();
+
+        for iteration in 0..config.iterations {
+            let mut change = 0.0f32;
+
+            for &(x, z) in &open {
+                let mut sum = 0.0;
+                let mut count = 0;
+
+                for (dx, dz) in NEIGHBORS {
+                    match cell(x + dx, z + dz) {
+                        Cell::Open => {
+                            sum += potential[&(x + dx, z + dz)];
+                            count += 1;
+                        }
+                        Cell::Vent(velocity) => sum -= velocity * tile_size,
+                        Cell::Outlet => sum += outlet * tile_size,
+                        Cell::Solid => {}
+                    }
+                }
+
+                if count == 0 {
+                    continue;
+                }
+
+                let value = sum / count as f32;
+                let previous = potential.insert((x, z), value).unwrap_or(0.0);
+                change = change.max((value - previous).abs());
+            }
+
+            if change < config.tolerance {
+                log::debug!("Airflow converged after {} iterations", iteration + 1);
+                break;
+            }
+        }
+
+        // Outward velocity through the face between (x, z) and its neighbour.
+        let face = |x: i32, z: i32, dx: i32, dz: i32| match cell(x + dx, z + dz) {
+            Cell::Open => (potential[&(x + dx, z + dz)] - potential[&(x, z)]) / tile_size,
+            Cell::Vent(velocity) => -velocity,
+            Cell::Outlet => outlet,
+            Cell::Solid => 0.0,
+        };
+
+        airflow.velocities = open
+            .iter()
+            .map(|&(x, z)| {
+                let velocity = Vec3::new(
+                    (face(x, z, 1, 0) - face(x, z, -1, 0)) / 2.0,
+                    0.0,
+                    (face(x, z, 0, 1) - face(x, z, 0, -1)) / 2.0,
+                );
+
+                ((x, z), velocity)
+            })
+            .collect();
+
+        airflow
+    }
+
+    // Bilinear interpolation between the centres of the surrounding open tiles.
+    pub fn velocity(&self, position: Vec3) -> Vec3 {
+        let u = position.x / self.tile_size - 0.5;
+        let v = position.z / self.tile_size - 0.5;
+        let (x, z) = (u.floor(), v.floor());
+        let (fx, fz) = (u - x, v - z);
+
+        let corners = [
+            ((0, 0), (1.0 - fx) * (1.0 - fz)),
+            ((1, 0), fx * (1.0 - fz)),
+            ((0, 1), (1.0 - fx) * fz),
+            ((1, 1), fx * fz),
+        ];
+
+        let (velocity, weight) = corners
+            .iter()
+            .filter_map(|((dx, dz), weight)| {
+                self.velocities
+                    .get(&(x as i32 + dx, z as i32 + dz))
+                    .map(|velocity| (*velocity * *weight, *weight))
+            })
+            .fold((Vec3::ZERO, 0.0), |(sum, total), (velocity, weight)| {
+                (sum + velocity, total + weight)
+            });
+
+        if weight > 0.0 {
+            velocity / weight
+        } else {
+            Vec3::ZERO
+        }
+    }
+
+    // Drag towards the background flow. Only horizontal motion is dragged so
+    // buoyancy is left alone.
+    pub fn acceleration(&self, position: Vec3, velocity: Vec3) -> Vec3 {
+        if self.velocities.is_empty() {
+            return Vec3::ZERO;
+        }
+
+        self.drag * (self.velocity(position) - velocity) * Vec3::new(1.0, 0.0, 1.0)
+    }
+}
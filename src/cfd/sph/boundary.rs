@@ -0,0 +1,233 @@
+use glam::Vec3;
+use std::collections::{HashMap, HashSet};
+
+use crate::cfd::config::{BoundaryConfig, Slip, SurfaceThermalConfig};
+use crate::{Tile, WorldMap};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Surface {
+    Wall,
+    Floor,
+    Ceiling,
+    Heater,
+    Window,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Contact {
+    pub surface: Surface,
+    // Points from the surface towards the particle.
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+pub struct Boundary {
+    walls: HashMap<(i32, i32), Surface>,
+    heaters: HashSet<(i32, i32)>,
+    tile_size: f32,
+    height: f32,
+    config: BoundaryConfig,
+}
+
+impl Boundary {
+    pub fn new(world_map: &WorldMap, config: BoundaryConfig) -> Self {
+        let walls = world_map
+            .iter_tiles()
+            .filter_map(|(x, z, tile)| match tile {
+                Tile::Wall | Tile::Inlet | Tile::Outlet | Tile::Vent(_) => {
+                    Some(((x as i32, z as i32), Surface::Wall))
+                }
+                Tile::Window => Some(((x as i32, z as i32), Surface::Window)),
+                _ => None,
+            })
+            .collect();
+
+        let heaters = world_map
+            .iter_tiles()
+            .filter(|(_, _, tile)| matches!(tile, Tile::Heater))
+            .map(|(x, z, _)| (x as i32, z as i32))
+            .collect();
+
+        Self {
+            walls,
+            heaters,
+            tile_size: world_map.get_tile_size(),
+            height: world_map.get_ceiling_height(),
+            config,
+        }
+    }
+
+    fn is_wall(&self, x: i32, z: i32) -> bool {
+        self.walls.contains_key(&(x, z))
+    }
+
+    fn tile(&self, position: Vec3) -> (i32, i32) {
+        (
+            (position.x / self.tile_size).floor() as i32,
+            (position.z / self.tile_size).floor() as i32,
+        )
+    }
+
+    // Every solid surface closer than `radius` to `position`.
+    pub fn contacts(&self, position: Vec3, radius: f32) -> Vec<Contact> {
+        let mut contacts = Vec::new();
+
+        let (x, z) = self.tile(position);
+
+        if position.y < radius {
+            contacts.push(Contact {
+                surface: match self.heaters.contains(&(x, z)) {
+                    true => Surface::Heater,
+                    false => Surface::Floor,
+                },
+                normal: Vec3::Y,
+                distance: position.y,
+            });
+        }
+
+        if self.height - position.y < radius {
+            contacts.push(Contact {
+                surface: Surface::Ceiling,
+                normal: -Vec3::Y,
+                distance: self.height - position.y,
+            });
+        }
+
+        let reach = (radius / self.tile_size).ceil() as i32;
+
+        for dx in -reach..=reach {
+            for dz in -reach..=reach {
+                let surface = match self.walls.get(&(x + dx, z + dz)) {
+                    None => continue,
+                    Some(surface) => *surface,
+                };
+
+                let min = Vec3::new((x + dx) as f32, 0.0, (z + dz) as f32) * self.tile_size;
+                let max = min + Vec3::new(self.tile_size, self.height, self.tile_size);
+                let closest = position.clamp(min, max);
+                let diff = position - closest;
+                let distance = diff.length();
+
+                if distance > 0.0 && distance < radius {
+                    contacts.push(Contact {
+                        surface,
+                        normal: diff / distance,
+                        distance,
+                    });
+                }
+            }
+        }
+
+        contacts
+    }
+
+    // Repulsive penalty acceleration from every surface within `radius`, with
+    // wall friction on the tangential velocity when no-slip is selected.
+    pub fn acceleration(&self, position: Vec3, velocity: Vec3, radius: f32) -> Vec3 {
+        self.contacts(position, radius)
+            .iter()
+            .map(|contact| {
+                let penetration = radius - contact.distance;
+                let mut acceleration = self.config.stiffness * penetration * contact.normal;
+
+                if self.config.slip == Slip::NoSlip {
+                    let tangential = velocity - velocity.dot(contact.normal) * contact.normal;
+                    acceleration -= self.config.friction * penetration / radius * tangential;
+                }
+
+                acceleration
+            })
+            .fold(Vec3::ZERO, |sum, acceleration| sum + acceleration)
+    }
+
+    // Rate of temperature change from Newton's law of cooling against every
+    // surface within `radius`, weighted by proximity.
+    pub fn heat_transfer(&self, position: Vec3, temperature: f32, radius: f32) -> f32 {
+        self.contacts(position, radius)
+            .iter()
+            .map(|contact| {
+                let surface = self.thermal(contact.surface);
+                let weight = 1.0 - contact.distance / radius;
+
+                surface.heat_transfer * weight * (surface.temperature - temperature)
+            })
+            .sum()
+    }
+
+    fn thermal(&self, surface: Surface) -> SurfaceThermalConfig {
+        let thermal = &self.config.thermal;
+
+        match surface {
+            Surface::Wall => thermal.wall,
+            Surface::Floor => thermal.floor,
+            Surface::Ceiling => thermal.ceiling,
+            Surface::Heater => thermal.heater,
+            Surface::Window => thermal.window,
+        }
+    }
+
+    // Pushes a particle that ended up inside a solid back onto its surface and
+    // reflects its normal velocity.
+    pub fn collide(&self, position: &mut Vec3, velocity: &mut Vec3) {
+        for _ in 0..3 {
+            let normal = match self.penetration(position) {
+                None => return,
+                Some(normal) => normal,
+            };
+
+            let normal_velocity = velocity.dot(normal);
+
+            if normal_velocity < 0.0 {
+                let tangential = *velocity - normal_velocity * normal;
+
+                *velocity = -self.config.restitution * normal_velocity * normal
+                    + match self.config.slip {
+                        Slip::FreeSlip => tangential,
+                        Slip::NoSlip => Vec3::ZERO,
+                    };
+            }
+        }
+    }
+
+    fn penetration(&self, position: &mut Vec3) -> Option<Vec3> {
+        if position.y < 0.0 {
+            position.y = 0.0;
+            return Some(Vec3::Y);
+        }
+
+        if position.y > self.height {
+            position.y = self.height;
+            return Some(-Vec3::Y);
+        }
+
+        let (x, z) = self.tile(*position);
+
+        if !self.is_wall(x, z) {
+            return None;
+        }
+
+        let min = Vec3::new(x as f32, 0.0, z as f32) * self.tile_size;
+        let max = min + self.tile_size;
+
+        let faces = [
+            (position.x - min.x, -Vec3::X, (x - 1, z)),
+            (max.x - position.x, Vec3::X, (x + 1, z)),
+            (position.z - min.z, -Vec3::Z, (x, z - 1)),
+            (max.z - position.z, Vec3::Z, (x, z + 1)),
+        ];
+
+        let nearest = |open: bool| {
+            faces
+                .iter()
+                .filter(move |(_, _, (nx, nz))| !open || !self.is_wall(*nx, *nz))
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .copied()
+        };
+
+        let (distance, normal, _) = nearest(true).or_else(|| nearest(false))?;
+
+        *position += normal * (distance + 1e-4);
+
+        Some(normal)
+    }
+}
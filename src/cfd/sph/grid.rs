@@ -0,0 +1,57 @@
+use glam::Vec3;
+use std::collections::HashMap;
+
+type Cell = (i32, i32, i32);
+
+pub struct Grid {
+    cell_size: f32,
+    cells: HashMap<Cell, Vec<usize>>,
+}
+
+impl Grid {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    pub fn rebuild<I>(&mut self, positions: I)
+    where
+        I: Iterator<Item = Vec3>,
+    {
+        self.cells.values_mut().for_each(|cell| cell.clear());
+
+        // Particles that blew up to non-finite positions are left out.
+        for (idx, position) in positions.enumerate().filter(|(_, p)| p.is_finite()) {
+            let cell = self.cell(position);
+            self.cells.entry(cell).or_default().push(idx);
+        }
+
+        self.cells.retain(|_, cell| !cell.is_empty());
+    }
+
+    pub fn candidates(&self, position: Vec3) -> impl Iterator<Item = usize> + '_ {
+        // Huge positions saturate to the outermost cell, whose neighbors must
+        // not overflow.
+        let (x, y, z) = self.cell(position);
+
+        (-1..=1)
+            .flat_map(move |dx| (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| (dx, dy, dz))))
+            .filter_map(move |(dx, dy, dz)| {
+                let cell = (
+                    x.saturating_add(dx),
+                    y.saturating_add(dy),
+                    z.saturating_add(dz),
+                );
+                self.cells.get(&cell)
+            })
+            .flat_map(|cell| cell.iter().copied())
+    }
+
+    fn cell(&self, position: Vec3) -> Cell {
+        let cell = (position / self.cell_size).floor();
+
+        (cell.x as i32, cell.y as i32, cell.z as i32)
+    }
+}
@@ -0,0 +1,80 @@
+use crate::cfd::config::IntegratorKind;
+use crate::cfd::sph::simulation::SimulationParticle;
+
+pub trait Integrator: Send + Sync {
+    // Advances the particle before forces are evaluated, using the acceleration
+    // from the previous step.
+    fn predict(&self, particle: &mut SimulationParticle, time_step: f32);
+
+    // Completes the step once `particle.acceleration` holds the new acceleration.
+    fn correct(&self, particle: &mut SimulationParticle, time_step: f32);
+}
+
+pub fn from_kind(kind: IntegratorKind) -> Box<dyn Integrator> {
+    match kind {
+        IntegratorKind::SymplecticEuler => Box::new(SymplecticEuler),
+        IntegratorKind::Leapfrog => Box::new(Leapfrog),
+        IntegratorKind::VelocityVerlet => Box::new(VelocityVerlet),
+        IntegratorKind::PredictorCorrector => Box::new(PredictorCorrector),
+    }
+}
+
+pub struct SymplecticEuler;
+
+impl Integrator for SymplecticEuler {
+    fn predict(&self, _particle: &mut SimulationParticle, _time_step: f32) {}
+
+    fn correct(&self, particle: &mut SimulationParticle, time_step: f32) {
+        particle.velocity += particle.acceleration * time_step;
+        particle.position += particle.velocity * time_step;
+    }
+}
+
+// Kick-drift-kick.
+pub struct Leapfrog;
+
+impl Integrator for Leapfrog {
+    fn predict(&self, particle: &mut SimulationParticle, time_step: f32) {
+        particle.velocity += particle.acceleration * time_step / 2.0;
+        particle.position += particle.velocity * time_step;
+    }
+
+    fn correct(&self, particle: &mut SimulationParticle, time_step: f32) {
+        particle.velocity += particle.acceleration * time_step / 2.0;
+    }
+}
+
+pub struct VelocityVerlet;
+
+impl Integrator for VelocityVerlet {
+    fn predict(&self, particle: &mut SimulationParticle, time_step: f32) {
+        particle.start_acceleration = particle.acceleration;
+        particle.position +=
+            particle.velocity * time_step + particle.acceleration / 2.0 * time_step * time_step;
+    }
+
+    fn correct(&self, particle: &mut SimulationParticle, time_step: f32) {
+        particle.velocity +=
+            (particle.start_acceleration + particle.acceleration) / 2.0 * time_step;
+    }
+}
+
+// Monaghan's predictor-corrector: forces are evaluated at the half step.
+pub struct PredictorCorrector;
+
+impl Integrator for PredictorCorrector {
+    fn predict(&self, particle: &mut SimulationParticle, time_step: f32) {
+        particle.start_position = particle.position;
+        particle.start_velocity = particle.velocity;
+        particle.position += particle.velocity * time_step / 2.0;
+        particle.velocity += particle.acceleration * time_step / 2.0;
+    }
+
+    fn correct(&self, particle: &mut SimulationParticle, time_step: f32) {
+        let half_velocity = particle.start_velocity + particle.acceleration * time_step / 2.0;
+        let half_position = particle.start_position + half_velocity * time_step / 2.0;
+
+        particle.velocity = 2.0 * half_velocity - particle.start_velocity;
+        particle.position = 2.0 * half_position - particle.start_position;
+    }
+}
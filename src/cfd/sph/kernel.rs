@@ -1,27 +1,58 @@
 use glam::Vec3;
 use std::f32::consts::PI;
+use strum::IntoEnumIterator;
 
-struct Poly6 {
+use crate::cfd::config::{KernelConfig, KernelKind};
+
+pub trait SmoothingKernel: Send + Sync {
+    fn w(&self, r: Vec3) -> f32;
+    fn grad_w(&self, r: Vec3) -> Vec3;
+    fn laplacian_w(&self, r: Vec3) -> f32;
+}
+
+pub fn from_kind(kind: KernelKind, radius: f32) -> Box<dyn SmoothingKernel> {
+    match kind {
+        KernelKind::Poly6 => Box::new(Poly6::new(radius)),
+        KernelKind::Spiky => Box::new(Spiky::new(radius)),
+        KernelKind::Viscosity => Box::new(Viscosity::new(radius)),
+        KernelKind::CubicSpline => Box::new(CubicSpline::new(radius)),
+        KernelKind::WendlandC2 => Box::new(WendlandC2::new(radius)),
+        KernelKind::WendlandC4 => Box::new(WendlandC4::new(radius)),
+    }
+}
+
+pub struct Poly6 {
     radius_sqr: f32,
     k: f32,
     l: f32,
 }
 
-struct Spiky {
+pub struct Spiky {
     radius: f32,
+    k: f32,
     l: f32,
+    m: f32,
 }
 
-struct Viscosity {
+pub struct Viscosity {
     radius: f32,
-    l: f32,
+    k: f32,
+    m: f32,
 }
 
-pub struct Kernel {
-    w0: f32,
-    poly6: Poly6,
-    spiky: Spiky,
-    viscosity: Viscosity,
+pub struct CubicSpline {
+    radius: f32,
+    k: f32,
+}
+
+pub struct WendlandC2 {
+    radius: f32,
+    k: f32,
+}
+
+pub struct WendlandC4 {
+    radius: f32,
+    k: f32,
 }
 
 impl Poly6 {
@@ -32,25 +63,39 @@ impl Poly6 {
             l: -945.0 / (32.0 * PI * radius.powf(9.0)),
         }
     }
+}
+
+impl SmoothingKernel for Poly6 {
+    fn w(&self, r: Vec3) -> f32 {
+        let r2 = r.dot(r);
 
-    pub fn w(&self, r: f32) -> f32 {
-        let r2 = r * r;
+        if r2 > self.radius_sqr {
+            return 0.0;
+        }
 
         self.k * (self.radius_sqr - r2).powf(3.0)
     }
 
-    pub fn w_vec(&self, r: Vec3) -> f32 {
+    fn grad_w(&self, r: Vec3) -> Vec3 {
         let r2 = r.dot(r);
 
-        self.k * (self.radius_sqr - r2).powf(3.0)
+        if r2 > self.radius_sqr {
+            return Vec3::ZERO;
+        }
+
+        let hr = self.radius_sqr - r2;
+
+        r * self.l * hr * hr
     }
 
-    pub fn grad_w(&self, r: Vec3) -> Vec3 {
+    fn laplacian_w(&self, r: Vec3) -> f32 {
         let r2 = r.dot(r);
-        let hr = self.radius_sqr - r2;
-        let hr2 = hr * hr;
 
-        r * self.l * hr2
+        if r2 > self.radius_sqr {
+            return 0.0;
+        }
+
+        self.l * (self.radius_sqr - r2) * (3.0 * self.radius_sqr - 7.0 * r2)
     }
 }
 
@@ -58,43 +103,315 @@ impl Spiky {
     pub fn new(radius: f32) -> Self {
         Self {
             radius,
+            k: 15.0 / (PI * radius.powf(6.0)),
             l: -45.0 / (PI * radius.powf(6.0)),
+            m: 90.0 / (PI * radius.powf(6.0)),
+        }
+    }
+}
+
+impl SmoothingKernel for Spiky {
+    fn w(&self, r: Vec3) -> f32 {
+        let rl = r.length();
+
+        if rl > self.radius {
+            return 0.0;
         }
+
+        self.k * (self.radius - rl).powf(3.0)
     }
 
-    pub fn grad_w(&self, r: Vec3) -> Vec3 {
+    fn grad_w(&self, r: Vec3) -> Vec3 {
         let rl = r.length();
+
+        if rl <= 0.0 || rl > self.radius {
+            return Vec3::ZERO;
+        }
+
         let hr = self.radius - rl;
-        let hr2 = hr * hr;
 
-        self.l * hr2 * (r / rl)
+        self.l * hr * hr * (r / rl)
+    }
+
+    fn laplacian_w(&self, r: Vec3) -> f32 {
+        let rl = r.length();
+
+        if rl <= 0.0 || rl > self.radius {
+            return 0.0;
+        }
+
+        self.m * (self.radius - rl) * (2.0 * rl - self.radius) / rl
     }
 }
 
 impl Viscosity {
     pub fn new(radius: f32) -> Self {
-        Self { radius, l: 10.0 }
+        Self {
+            radius,
+            k: 15.0 / (2.0 * PI * radius.powf(3.0)),
+            m: 45.0 / (PI * radius.powf(6.0)),
+        }
     }
+}
 
-    pub fn laplacian_w(&self, r: Vec3) -> f32 {
+impl SmoothingKernel for Viscosity {
+    fn w(&self, r: Vec3) -> f32 {
         let rl = r.length();
+        let h = self.radius;
 
-        self.l * (self.radius * rl)
+        if rl <= 0.0 || rl > h {
+            return 0.0;
+        }
+
+        self.k * (-rl.powf(3.0) / (2.0 * h.powf(3.0)) + rl * rl / (h * h) + h / (2.0 * rl) - 1.0)
+    }
+
+    fn grad_w(&self, r: Vec3) -> Vec3 {
+        let rl = r.length();
+        let h = self.radius;
+
+        if rl <= 0.0 || rl > h {
+            return Vec3::ZERO;
+        }
+
+        self.k * r * (-3.0 * rl / (2.0 * h.powf(3.0)) + 2.0 / (h * h) - h / (2.0 * rl.powf(3.0)))
+    }
+
+    fn laplacian_w(&self, r: Vec3) -> f32 {
+        let rl = r.length();
+
+        if rl > self.radius {
+            return 0.0;
+        }
+
+        self.m * (self.radius - rl)
     }
 }
 
-impl Kernel {
+impl CubicSpline {
+    pub fn new(radius: f32) -> Self {
+        Self {
+            radius,
+            k: 8.0 / (PI * radius.powf(3.0)),
+        }
+    }
+}
+
+impl SmoothingKernel for CubicSpline {
+    fn w(&self, r: Vec3) -> f32 {
+        let q = r.length() / self.radius;
+
+        if q > 1.0 {
+            0.0
+        } else if q <= 0.5 {
+            self.k * (6.0 * (q * q * q - q * q) + 1.0)
+        } else {
+            self.k * 2.0 * (1.0 - q).powf(3.0)
+        }
+    }
+
+    fn grad_w(&self, r: Vec3) -> Vec3 {
+        let rl = r.length();
+        let q = rl / self.radius;
+
+        if rl <= 0.0 || q > 1.0 {
+            return Vec3::ZERO;
+        }
+
+        let dw = if q <= 0.5 {
+            6.0 * (3.0 * q * q - 2.0 * q)
+        } else {
+            -6.0 * (1.0 - q) * (1.0 - q)
+        };
+
+        self.k / self.radius * dw * (r / rl)
+    }
+
+    fn laplacian_w(&self, r: Vec3) -> f32 {
+        let rl = r.length();
+        let q = rl / self.radius;
+
+        if rl <= 0.0 || q > 1.0 {
+            return 0.0;
+        }
+
+        let lw = if q <= 0.5 {
+            72.0 * q - 36.0
+        } else {
+            12.0 * (1.0 - q) - 12.0 * (1.0 - q) * (1.0 - q) / q
+        };
+
+        self.k / (self.radius * self.radius) * lw
+    }
+}
+
+impl WendlandC2 {
+    pub fn new(radius: f32) -> Self {
+        Self {
+            radius,
+            k: 21.0 / (2.0 * PI * radius.powf(3.0)),
+        }
+    }
+}
+
+impl SmoothingKernel for WendlandC2 {
+    fn w(&self, r: Vec3) -> f32 {
+        let q = r.length() / self.radius;
+
+        if q > 1.0 {
+            return 0.0;
+        }
+
+        self.k * (1.0 - q).powf(4.0) * (1.0 + 4.0 * q)
+    }
+
+    fn grad_w(&self, r: Vec3) -> Vec3 {
+        let rl = r.length();
+        let q = rl / self.radius;
+
+        if rl <= 0.0 || q > 1.0 {
+            return Vec3::ZERO;
+        }
+
+        self.k / self.radius * -20.0 * q * (1.0 - q).powf(3.0) * (r / rl)
+    }
+
+    fn laplacian_w(&self, r: Vec3) -> f32 {
+        let q = r.length() / self.radius;
+
+        if q > 1.0 {
+            return 0.0;
+        }
+
+        self.k / (self.radius * self.radius) * 60.0 * (1.0 - q).powf(2.0) * (2.0 * q - 1.0)
+    }
+}
+
+impl WendlandC4 {
     pub fn new(radius: f32) -> Self {
-        let poly6 = Poly6::new(radius);
-        let spiky = Spiky::new(radius);
-        let viscosity = Viscosity::new(radius);
-        let w0 = poly6.w(0.0);
+        Self {
+            radius,
+            k: 495.0 / (32.0 * PI * radius.powf(3.0)),
+        }
+    }
+}
+
+impl SmoothingKernel for WendlandC4 {
+    fn w(&self, r: Vec3) -> f32 {
+        let q = r.length() / self.radius;
+
+        if q > 1.0 {
+            return 0.0;
+        }
+
+        self.k * (1.0 - q).powf(6.0) * (1.0 + 6.0 * q + 35.0 / 3.0 * q * q)
+    }
+
+    fn grad_w(&self, r: Vec3) -> Vec3 {
+        let rl = r.length();
+        let q = rl / self.radius;
+
+        if rl <= 0.0 || q > 1.0 {
+            return Vec3::ZERO;
+        }
+
+        let dw = -56.0 / 3.0 * q * (1.0 - q).powf(5.0) * (1.0 + 5.0 * q);
+
+        self.k / self.radius * dw * (r / rl)
+    }
+
+    fn laplacian_w(&self, r: Vec3) -> f32 {
+        let q = r.length() / self.radius;
+
+        if q > 1.0 {
+            return 0.0;
+        }
+
+        let lw = -56.0 * (1.0 - q).powf(4.0) * (1.0 + 4.0 * q - 15.0 * q * q);
+
+        self.k / (self.radius * self.radius) * lw
+    }
+}
+
+// Akinci et al. cohesion spline, attractive at long range and repulsive close in.
+pub struct Cohesion {
+    radius: f32,
+    k: f32,
+    c: f32,
+}
+
+impl Cohesion {
+    pub fn new(radius: f32) -> Self {
+        Self {
+            radius,
+            k: 32.0 / (PI * radius.powf(9.0)),
+            c: radius.powf(6.0) / 64.0,
+        }
+    }
+
+    pub fn c(&self, r: f32) -> f32 {
+        let h = self.radius;
+
+        if r <= 0.0 || r > h {
+            0.0
+        } else if 2.0 * r > h {
+            self.k * (h - r).powf(3.0) * r.powf(3.0)
+        } else {
+            self.k * (2.0 * (h - r).powf(3.0) * r.powf(3.0) - self.c)
+        }
+    }
+}
+
+// Akinci et al. adhesion spline, non-zero only in the outer half of the support.
+pub struct Adhesion {
+    radius: f32,
+    k: f32,
+}
+
+impl Adhesion {
+    pub fn new(radius: f32) -> Self {
+        Self {
+            radius,
+            k: 0.007 / radius.powf(3.25),
+        }
+    }
+
+    pub fn a(&self, r: f32) -> f32 {
+        let h = self.radius;
+
+        if 2.0 * r <= h || r > h {
+            return 0.0;
+        }
+
+        self.k * (-4.0 * r * r / h + 6.0 * r - 2.0 * h).max(0.0).powf(0.25)
+    }
+}
+
+pub struct Kernel {
+    w0: f32,
+    density: Box<dyn SmoothingKernel>,
+    pressure: Box<dyn SmoothingKernel>,
+    viscosity: Box<dyn SmoothingKernel>,
+    cohesion: Cohesion,
+    adhesion: Adhesion,
+}
+
+impl Kernel {
+    pub fn new(radius: f32, config: &KernelConfig) -> Self {
+        let density = from_kind(config.density, radius);
+        let pressure = from_kind(config.pressure, radius);
+        let viscosity = from_kind(config.viscosity, radius);
+        let w0 = density.w(Vec3::ZERO);
+        let cohesion = Cohesion::new(radius);
+        let adhesion = Adhesion::new(radius);
 
         Self {
             w0,
-            poly6,
-            spiky,
+            density,
+            pressure,
             viscosity,
+            cohesion,
+            adhesion,
         }
     }
 
@@ -103,18 +420,142 @@ impl Kernel {
     }
 
     pub fn w(&self, r: Vec3) -> f32 {
-        self.poly6.w_vec(r)
+        self.density.w(r)
     }
 
-    pub fn poly6_grad_w(&self, r: Vec3) -> Vec3 {
-        self.poly6.grad_w(r)
+    pub fn density_grad_w(&self, r: Vec3) -> Vec3 {
+        self.density.grad_w(r)
     }
 
-    pub fn spiky_grad_w(&self, r: Vec3) -> Vec3 {
-        self.spiky.grad_w(r)
+    pub fn pressure_grad_w(&self, r: Vec3) -> Vec3 {
+        self.pressure.grad_w(r)
     }
 
     pub fn viscosity_laplacian_w(&self, r: Vec3) -> f32 {
         self.viscosity.laplacian_w(r)
     }
+
+    pub fn cohesion(&self, r: f32) -> f32 {
+        self.cohesion.c(r)
+    }
+
+    pub fn adhesion(&self, r: f32) -> f32 {
+        self.adhesion.a(r)
+    }
+}
+
+#[derive(Debug)]
+pub struct KernelCheck {
+    pub kind: KernelKind,
+    pub integral: f64,
+    pub gradient_error: f64,
+    pub laplacian_error: f64,
+}
+
+impl KernelCheck {
+    const TOLERANCE: f64 = 1e-2;
+
+    pub fn passed(&self) -> bool {
+        (self.integral - 1.0).abs() < Self::TOLERANCE
+            && self.gradient_error < Self::TOLERANCE
+            && self.laplacian_error < Self::TOLERANCE
+    }
+}
+
+// Integrates each kernel over its support and compares its derivatives against
+// central finite differences of its value, sampled away from the origin where
+// the singular kernels are not differentiable.
+pub fn check_kernels(radius: f32) -> Vec<KernelCheck> {
+    KernelKind::iter()
+        .map(|kind| {
+            let kernel = from_kind(kind, radius);
+
+            KernelCheck {
+                kind,
+                integral: integrate(kernel.as_ref(), radius),
+                gradient_error: gradient_error(kernel.as_ref(), radius),
+                laplacian_error: laplacian_error(kernel.as_ref(), radius),
+            }
+        })
+        .collect()
+}
+
+fn integrate(kernel: &dyn SmoothingKernel, radius: f32) -> f64 {
+    let samples = 100_000;
+    let dr = radius as f64 / samples as f64;
+
+    (0..samples)
+        .map(|i| {
+            let r = (i as f64 + 0.5) * dr;
+            4.0 * std::f64::consts::PI * r * r * kernel.w(Vec3::new(r as f32, 0.0, 0.0)) as f64 * dr
+        })
+        .sum()
+}
+
+fn sample_points(radius: f32) -> impl Iterator<Item = Vec3> {
+    let directions = [
+        Vec3::X,
+        Vec3::new(1.0, 1.0, 0.0).normalize(),
+        Vec3::new(-1.0, 2.0, 3.0).normalize(),
+    ];
+
+    directions.into_iter().flat_map(move |direction| {
+        (4..20)
+            .map(move |i| i as f32 / 20.0)
+            .filter(|q| (q - 0.5).abs() > 0.05)
+            .map(move |q| direction * q * radius)
+    })
+}
+
+fn gradient_error(kernel: &dyn SmoothingKernel, radius: f32) -> f64 {
+    let e = radius * 1e-3;
+    let scale = max_abs(sample_points(radius).map(|r| kernel.grad_w(r).length()));
+
+    max_abs(sample_points(radius).map(|r| {
+        let numeric = Vec3::new(
+            kernel.w(r + Vec3::X * e) - kernel.w(r - Vec3::X * e),
+            kernel.w(r + Vec3::Y * e) - kernel.w(r - Vec3::Y * e),
+            kernel.w(r + Vec3::Z * e) - kernel.w(r - Vec3::Z * e),
+        ) / (2.0 * e);
+
+        (numeric - kernel.grad_w(r)).length()
+    })) / scale
+}
+
+fn laplacian_error(kernel: &dyn SmoothingKernel, radius: f32) -> f64 {
+    let e = radius * 1e-3;
+    let scale = max_abs(sample_points(radius).map(|r| kernel.laplacian_w(r)));
+
+    max_abs(sample_points(radius).map(|r| {
+        let numeric = [Vec3::X, Vec3::Y, Vec3::Z]
+            .iter()
+            .map(|axis| {
+                let forward = kernel.grad_w(r + *axis * e).dot(*axis);
+                let backward = kernel.grad_w(r - *axis * e).dot(*axis);
+                (forward - backward) / (2.0 * e)
+            })
+            .sum::<f32>();
+
+        numeric - kernel.laplacian_w(r)
+    })) / scale
+}
+
+fn max_abs<I: Iterator<Item = f32>>(values: I) -> f64 {
+    values.map(|value| value.abs() as f64).fold(0.0, f64::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernels_pass_checks() {
+        for radius in [0.0457, 0.1, 1.0] {
+            let checks = check_kernels(radius);
+
+            for check in &checks {
+                assert!(check.passed(), "radius {}: {:?}", radius, check);
+            }
+        }
+    }
 }
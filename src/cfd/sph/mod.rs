@@ -1,2 +1,7 @@
-mod kernel;
+mod airflow;
+mod boundary;
+mod grid;
+mod integrator;
+pub mod kernel;
+pub mod particle_id;
 pub mod simulation;
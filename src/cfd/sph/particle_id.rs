@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// Generational handle to a particle. The generation changes whenever the id
+// slot is reused, so a stale id never resolves to a newer particle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct ParticleId {
+    index: u32,
+    generation: u32,
+}
+
+impl fmt::Display for ParticleId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}v{}", self.index, self.generation)
+    }
+}
+
+impl FromStr for ParticleId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid particle id {}, expected e.g. 3v0", s);
+        let (index, generation) = s.split_once('v').ok_or_else(invalid)?;
+
+        Ok(Self {
+            index: index.parse().map_err(|_| invalid())?,
+            generation: generation.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Slot {
+    generation: u32,
+    position: Option<usize>,
+}
+
+// Maps particle ids to their current position in the particle storage.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ParticleIds {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+}
+
+impl ParticleIds {
+    pub fn insert(&mut self, position: usize) -> ParticleId {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.position = Some(position);
+
+                ParticleId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    position: Some(position),
+                });
+
+                ParticleId {
+                    index: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    pub fn position(&self, id: ParticleId) -> Option<usize> {
+        self.slots
+            .get(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.position)
+    }
+
+    pub fn relocate(&mut self, id: ParticleId, position: usize) {
+        self.slots[id.index as usize].position = Some(position);
+    }
+
+    pub fn remove(&mut self, id: ParticleId) {
+        let slot = &mut self.slots[id.index as usize];
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.position = None;
+        self.free.push(id.index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_id_does_not_resolve_after_reuse() {
+        let mut ids = ParticleIds::default();
+        let stale = ids.insert(0);
+        ids.remove(stale);
+        let fresh = ids.insert(0);
+
+        assert_eq!(stale.index, fresh.index);
+        assert_eq!(ids.position(stale), None);
+        assert_eq!(ids.position(fresh), Some(0));
+    }
+
+    #[test]
+    fn parses_displayed_id() {
+        let id = ParticleId {
+            index: 12,
+            generation: 3,
+        };
+
+        assert_eq!(id.to_string().parse(), Ok(id));
+        assert!("12".parse::<ParticleId>().is_err());
+        assert!("av3".parse::<ParticleId>().is_err());
+    }
+}
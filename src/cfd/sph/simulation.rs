@@ -1,19 +1,45 @@
 use glam::Vec3;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-use crate::cfd::config::{Config, FluidType, SimulationConfig};
+use crate::cfd::config::{
+    AdaptiveStepConfig, BoundaryHandling, BudgetPolicy, Config, Decay, EquationOfState, FluidType,
+    LiquidConfig, Material, NeighborSearch, PhaseCoupling, PressureSolver, ScalarConfig,
+    SimulationConfig, DEFAULT_MATERIAL,
+};
+use crate::cfd::sph::airflow::Airflow;
+use crate::cfd::sph::boundary::{Boundary, Surface};
+use crate::cfd::sph::grid::Grid;
+use crate::cfd::sph::integrator::{self, Integrator};
 use crate::cfd::sph::kernel::Kernel;
+use crate::cfd::sph::particle_id::{ParticleId, ParticleIds};
 use crate::{ParticleInstance, Tile, WorldMap};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimulationParticle {
+    id: ParticleId,
     pub position: Vec3,
-    velocity: Vec3,
-    acceleration: Vec3,
+    pub(super) velocity: Vec3,
+    pub(super) acceleration: Vec3,
+    pub(super) start_position: Vec3,
+    pub(super) start_velocity: Vec3,
+    pub(super) start_acceleration: Vec3,
     forces: Vec3,
     density: f32,
     density_correction: f32,
+    normal: Vec3,
     temperature: f32,
     fluid_type: FluidType,
+    material: Material,
+    material_name: String,
+    scalars: Vec<f32>,
+    emitted_at: f32,
+    age: f32,
+    lifetime: Option<f32>,
+    decay: Decay,
+    source: Option<char>,
     size: f32,
     color: Vec3,
 }
@@ -26,247 +52,1386 @@ impl SimulationParticle {
         fluid_type: FluidType,
         size: f32,
         color: Vec3,
+        material: Material,
     ) -> Self {
         Self {
+            id: ParticleId::default(),
             position,
             velocity,
             acceleration: Vec3::ZERO,
+            start_position: position,
+            start_velocity: velocity,
+            start_acceleration: Vec3::ZERO,
             forces: Vec3::ZERO,
             density: 0.0,
             density_correction: 0.0,
+            normal: Vec3::ZERO,
             temperature,
             fluid_type,
+            material,
+            material_name: String::new(),
+            scalars: Vec::new(),
+            emitted_at: 0.0,
+            age: 0.0,
+            lifetime: None,
+            decay: Decay::Constant,
+            source: None,
             size,
             color,
         }
     }
+
+    pub fn with_scalars(mut self, scalars: Vec<f32>) -> Self {
+        self.scalars = scalars;
+        self
+    }
+
+    pub fn with_material_name(mut self, material_name: String) -> Self {
+        self.material_name = material_name;
+        self
+    }
+
+    pub fn with_lifetime(mut self, lifetime: Option<f32>, decay: Decay) -> Self {
+        self.lifetime = lifetime;
+        self.decay = decay;
+        self
+    }
+
+    pub fn with_source(mut self, source: char) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    fn is_expired(&self) -> bool {
+        self.lifetime.is_some_and(|lifetime| self.age >= lifetime)
+    }
+
+    fn decay_factor(&self, age: f32) -> f32 {
+        match (self.decay, self.lifetime) {
+            (Decay::Constant, _) | (Decay::Linear, None) => 1.0,
+            (Decay::Linear, Some(lifetime)) => (1.0 - age / lifetime).max(0.0),
+            (Decay::Exponential { half_life }, _) => 0.5f32.powf(age / half_life),
+        }
+    }
+
+    // Absorbs `other` conserving mass and momentum. Temperature and scalars are
+    // mass-weighted averages.
+    fn merge(&mut self, other: &SimulationParticle) {
+        let mass = self.material.mass + other.material.mass;
+        let weight = other.material.mass / mass;
+
+        self.position = self.position.lerp(other.position, weight);
+        self.velocity = self.velocity.lerp(other.velocity, weight);
+        self.temperature += (other.temperature - self.temperature) * weight;
+        self.scalars
+            .iter_mut()
+            .zip(other.scalars.iter())
+            .for_each(|(scalar, other)| *scalar += (other - *scalar) * weight);
+        self.material.mass = mass;
+    }
+
+    fn instance(&self) -> ParticleInstance {
+        ParticleInstance {
+            position: self.position,
+            size: self.size * self.decay_factor(self.age),
+            color: self.color,
+        }
+    }
+
+    pub fn get_id(&self) -> ParticleId {
+        self.id
+    }
+
+    pub fn get_scalars(&self) -> &Vec<f32> {
+        &self.scalars
+    }
+
+    pub fn get_material_name(&self) -> &String {
+        &self.material_name
+    }
+
+    pub fn get_mass(&self) -> f32 {
+        self.material.mass
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SolverStats {
+    pub iterations: usize,
+    pub residual: f32,
+}
+
+// Particles removed for leaving the room and for reaching the end of their
+// lifetime. Sinks keep their own accounting.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct ParticleLosses {
+    pub walls: usize,
+    pub expired: usize,
+}
+
+// Emissions distorted by the particle budget.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct BudgetStats {
+    pub throttled: usize,
+    pub recycled: usize,
+    pub merged: usize,
+}
+
+// Everything that changes while stepping. Neighbors and densities are
+// recomputed by the next step.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SimulationState {
+    time: f32,
+    scalar_names: Vec<String>,
+    particles: Vec<SimulationParticle>,
+    ids: ParticleIds,
+    losses: ParticleLosses,
+    budget: BudgetStats,
 }
 
 pub struct SPH {
     kernel: Kernel,
+    integrator: Box<dyn Integrator>,
+    boundary: Boundary,
+    airflow: Airflow,
+    grid: Grid,
     particles: Vec<SimulationParticle>,
+    ids: ParticleIds,
+    losses: ParticleLosses,
+    budget: BudgetStats,
+    caps: BTreeMap<char, usize>,
+    neighbors: Vec<Vec<usize>>,
     instances: Vec<ParticleInstance>,
     config: SimulationConfig,
+    scalars: Vec<ScalarConfig>,
+    scalar_names: Vec<String>,
+    pool: ThreadPool,
+    time: f32,
+    solver_stats: Option<SolverStats>,
 }
 
 impl SPH {
-    pub fn new(config: &Config) -> Self {
+    pub fn new(config: &Config, world_map: &WorldMap) -> Self {
+        let scalars = config.get_scalars();
+        let scalar_names = config.get_scalar_names();
+        let caps = config
+            .get_actuators()
+            .iter()
+            .filter_map(|(label, actuator)| actuator.max_particles.map(|cap| (*label, cap)))
+            .collect();
+        let liquids: BTreeMap<String, Material> = config
+            .get_actuators()
+            .values()
+            .filter(|actuator| actuator.fluid_type == FluidType::Liquid)
+            .map(|actuator| {
+                let name = config.get_material_name(actuator.material.as_ref());
+                (name, config.get_material(actuator.material.as_ref()))
+            })
+            .collect();
         let config = config.get_simulation_config().clone();
-        let kernel = Kernel::new(config.radius);
+
+        let kernel = Kernel::new(config.radius, &config.kernels);
+        let integrator = integrator::from_kind(config.integrator);
+        let boundary = Boundary::new(world_map, config.boundary);
+        let airflow = Airflow::new(world_map, config.airflow);
+        let grid = Grid::new(config.radius);
         let particles = Vec::new();
+        let neighbors = Vec::new();
         let instances = Vec::new();
+        if config.liquid.equation_of_state == EquationOfState::Tait {
+            for (name, material) in &liquids {
+                let rest_density = liquid_rest_density(&config.liquid, name, material);
+                let self_density = material.mass * kernel.w0();
+
+                if rest_density < self_density {
+                    log::warn!(
+                        "Rest density {} of liquid {} is below the density {} of an isolated particle",
+                        rest_density,
+                        name,
+                        self_density
+                    );
+                }
+            }
+        }
+
+        if config.adaptive_step.is_none()
+            && config.liquid.equation_of_state == EquationOfState::Tait
+        {
+            let limit =
+                AdaptiveStepConfig::default_cfl() * config.radius / config.liquid.speed_of_sound;
+
+            if config.step > limit {
+                log::warn!(
+                    "Time step {} exceeds the CFL limit {} for speed of sound {}",
+                    config.step,
+                    limit,
+                    config.liquid.speed_of_sound
+                );
+            }
+        }
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(config.threads.unwrap_or(0))
+            .build()
+            .expect("Could not build thread pool");
 
         Self {
             kernel,
+            integrator,
+            boundary,
+            airflow,
+            grid,
             particles,
+            ids: ParticleIds::default(),
+            losses: ParticleLosses::default(),
+            budget: BudgetStats::default(),
+            caps,
+            neighbors,
             instances,
             config,
+            scalars,
+            scalar_names,
+            pool,
+            time: 0.0,
+            solver_stats: None,
         }
     }
 
-    pub fn add_particle(&mut self, particle: SimulationParticle) {
-        let position = particle.position.clone();
-        let size = particle.size;
-        let color = particle.color.clone();
-        self.particles.push(particle);
-        self.instances.push(ParticleInstance {
-            position,
-            size,
-            color,
+    // Called when the map changes, e.g. a door opens or closes.
+    pub fn rebuild_boundaries(&mut self, world_map: &WorldMap) {
+        self.boundary = Boundary::new(world_map, self.config.boundary);
+        self.airflow = Airflow::new(world_map, self.config.airflow);
+    }
+
+    pub fn save_state(&self) -> SimulationState {
+        SimulationState {
+            time: self.time,
+            scalar_names: self.scalar_names.clone(),
+            particles: self.particles.clone(),
+            ids: self.ids.clone(),
+            losses: self.losses,
+            budget: self.budget,
+        }
+    }
+
+    // Concentrations are matched by scalar name, so the checkpoint may have been
+    // written with scalars that were since added, removed or reordered. Added
+    // scalars start at zero.
+    pub fn restore_state(&mut self, mut state: SimulationState) {
+        state
+            .scalar_names
+            .iter()
+            .filter(|name| !self.scalar_names.contains(name))
+            .for_each(|name| log::warn!("Dropping scalar {} from the checkpoint", name));
+
+        let sources: Vec<Option<usize>> = self
+            .scalar_names
+            .iter()
+            .map(|name| state.scalar_names.iter().position(|source| source == name))
+            .collect();
+
+        state.particles.iter_mut().for_each(|particle| {
+            particle.scalars = sources
+                .iter()
+                .map(|source| {
+                    source
+                        .and_then(|k| particle.scalars.get(k).copied())
+                        .unwrap_or(0.0)
+                })
+                .collect();
         });
+
+        self.time = state.time;
+        self.particles = state.particles;
+        self.ids = state.ids;
+        self.losses = state.losses;
+        self.budget = state.budget;
+        self.instances = self
+            .particles
+            .iter()
+            .map(SimulationParticle::instance)
+            .collect();
+    }
+
+    pub fn add_particle(&mut self, mut particle: SimulationParticle) -> ParticleId {
+        let id = self.ids.insert(self.particles.len());
+        particle.id = id;
+        particle.emitted_at = self.time;
+
+        self.instances.push(particle.instance());
+        self.particles.push(particle);
+
+        id
+    }
+
+    // Adds an emitted particle unless that exceeds the global or its actuator's
+    // budget, in which case the budget policy decides what happens.
+    pub fn emit_particle(&mut self, particle: SimulationParticle) -> Option<ParticleId> {
+        let source = particle.source;
+        let source_full = source
+            .and_then(|source| self.caps.get(&source))
+            .is_some_and(|cap| {
+                self.particles
+                    .iter()
+                    .filter(|other| other.source == source)
+                    .count()
+                    >= *cap
+            });
+        let global_full = self
+            .config
+            .budget
+            .max_particles
+            .is_some_and(|max| self.particles.len() >= max);
+
+        if !source_full && !global_full {
+            return Some(self.add_particle(particle));
+        }
+
+        // A full actuator only gives up its own particles.
+        let scope = if source_full { source } else { None };
+        let in_scope = |other: &SimulationParticle| scope.is_none() || other.source == scope;
+
+        match self.config.budget.policy {
+            BudgetPolicy::Throttle => {}
+            BudgetPolicy::RecycleOldest => {
+                let oldest = self
+                    .particles
+                    .iter()
+                    .filter(|other| in_scope(other))
+                    .min_by(|a, b| a.emitted_at.total_cmp(&b.emitted_at))
+                    .map(|other| other.id);
+
+                if let Some(oldest) = oldest {
+                    self.remove_particle(oldest);
+                    self.budget.recycled += 1;
+                    log::debug!(
+                        "Particle budget reached at {}s, recycled the oldest particle ({} in total)",
+                        self.time,
+                        self.budget.recycled
+                    );
+
+                    return Some(self.add_particle(particle));
+                }
+            }
+            BudgetPolicy::Merge => {
+                let nearest = self
+                    .particles
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, other)| {
+                        in_scope(other)
+                            && other.fluid_type == particle.fluid_type
+                            && other.material_name == particle.material_name
+                    })
+                    .min_by(|(_, a), (_, b)| {
+                        let a = a.position.distance_squared(particle.position);
+                        let b = b.position.distance_squared(particle.position);
+                        a.total_cmp(&b)
+                    })
+                    .map(|(idx, _)| idx);
+
+                if let Some(idx) = nearest {
+                    self.particles[idx].merge(&particle);
+                    self.budget.merged += 1;
+                    log::debug!(
+                        "Particle budget reached at {}s, merged the new particle ({} in total)",
+                        self.time,
+                        self.budget.merged
+                    );
+
+                    return Some(self.particles[idx].id);
+                }
+            }
+        }
+
+        self.budget.throttled += 1;
+        log::debug!(
+            "Particle budget reached at {}s, dropped the new particle ({} in total)",
+            self.time,
+            self.budget.throttled
+        );
+
+        None
     }
 
-    pub fn remove_particle(&mut self, index: usize) {
-        self.particles.remove(index);
-        self.instances.remove(index);
+    // Swaps the last particle into the freed position, which keeps removal
+    // O(1) but changes the position of that particle.
+    pub fn remove_particle(&mut self, id: ParticleId) -> Option<SimulationParticle> {
+        let position = self.ids.position(id)?;
+        self.ids.remove(id);
+
+        let particle = self.particles.swap_remove(position);
+        self.instances.swap_remove(position);
+
+        if let Some(moved) = self.particles.get(position) {
+            self.ids.relocate(moved.id, position);
+        }
+
+        Some(particle)
     }
 
     pub fn get_particles(&self) -> &Vec<SimulationParticle> {
         &self.particles
     }
 
+    // None once the particle has been removed.
+    pub fn get_particle(&self, id: ParticleId) -> Option<&SimulationParticle> {
+        self.ids
+            .position(id)
+            .map(|position| &self.particles[position])
+    }
+
     pub fn check_particles(&mut self, world_map: &WorldMap) {
-        self.particles
+        let removed = self
+            .particles
             .iter()
             .enumerate()
             .filter(
                 |(_, particle)| match world_map.get_tile_in_position(particle.position) {
-                    Tile::Floor => particle.position.y > 3.0 || particle.position.y < 0.0,
+                    Tile::Floor => match self.config.boundary.handling {
+                        BoundaryHandling::Solid => false,
+                        BoundaryHandling::Delete => {
+                            particle.position.y > world_map.get_ceiling_height()
+                                || particle.position.y < 0.0
+                        }
+                    },
+                    Tile::Wall => self.config.boundary.handling == BoundaryHandling::Delete,
                     _ => true,
                 },
             )
-            .map(|(idx, _)| idx)
-            .collect::<Vec<_>>()
+            .map(|(_, particle)| particle.id)
+            .collect::<Vec<_>>();
+
+        if !removed.is_empty() {
+            self.losses.walls += removed.len();
+            log::debug!(
+                "Removed {} particles outside the room, {} in total",
+                removed.len(),
+                self.losses.walls
+            );
+        }
+
+        removed.into_iter().for_each(|id| {
+            self.remove_particle(id);
+        });
+    }
+
+    // Sinks take the particles inside their capture volume, up to their
+    // extraction rate over the time step.
+    pub fn absorb_particles(&mut self, world_map: &mut WorldMap, time_step: f32) {
+        let mut absorbed = vec![false; self.particles.len()];
+
+        world_map.get_sinks().values_mut().for_each(|sink| {
+            sink.refill(time_step);
+
+            self.particles
+                .iter()
+                .zip(absorbed.iter_mut())
+                .for_each(|(particle, absorbed)| {
+                    if !*absorbed && sink.captures(particle.position) {
+                        *absorbed = sink.extract(particle);
+                    }
+                });
+        });
+
+        let absorbed = self
+            .particles
             .iter()
-            .rev()
-            .for_each(|idx| self.remove_particle(*idx));
+            .zip(absorbed)
+            .filter(|(_, absorbed)| *absorbed)
+            .map(|(particle, _)| particle.id)
+            .collect::<Vec<_>>();
+
+        absorbed.into_iter().for_each(|id| {
+            self.remove_particle(id);
+        });
     }
 
     pub fn get_particle_instances(&self) -> &Vec<ParticleInstance> {
         &self.instances
     }
 
-    pub fn step(&mut self, time_step: f32) {
+    pub fn get_time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn get_solver_stats(&self) -> Option<SolverStats> {
+        self.solver_stats
+    }
+
+    pub fn get_losses(&self) -> ParticleLosses {
+        self.losses
+    }
+
+    pub fn get_budget_stats(&self) -> BudgetStats {
+        self.budget
+    }
+
+    pub fn step(&mut self) -> f32 {
+        let time_step = self.time_step();
+
+        self.predict(time_step);
+        self.find_neighbors();
         self.compute_uncorrected_densities();
         self.compute_densities();
-        self.compute_forces();
-        self.integrate(time_step);
+        self.compute_normals();
+        self.compute_forces(time_step);
+        self.solve_pressure(time_step);
+        self.transport_scalars(time_step);
+        self.age_particles(time_step);
+        self.correct(time_step);
+        self.time += time_step;
+        self.retire_particles();
+
+        time_step
+    }
+
+    // Scales the scalar payload by the change of the decay curve over the step.
+    fn age_particles(&mut self, time_step: f32) {
+        self.pool.install(|| {
+            self.particles.par_iter_mut().for_each(|particle| {
+                let previous = particle.decay_factor(particle.age);
+                particle.age += time_step;
+
+                if previous > 0.0 {
+                    let ratio = particle.decay_factor(particle.age) / previous;
+                    particle
+                        .scalars
+                        .iter_mut()
+                        .for_each(|scalar| *scalar *= ratio);
+                }
+            })
+        });
+    }
+
+    fn retire_particles(&mut self) {
+        let expired = self
+            .particles
+            .iter()
+            .filter(|particle| particle.is_expired())
+            .map(|particle| particle.id)
+            .collect::<Vec<_>>();
+
+        if expired.is_empty() {
+            return;
+        }
+
+        self.losses.expired += expired.len();
+        log::debug!(
+            "Retired {} expired particles, {} in total",
+            expired.len(),
+            self.losses.expired
+        );
+
+        expired.into_iter().for_each(|id| {
+            self.remove_particle(id);
+        });
+    }
+
+    fn speed_of_sound(&self) -> f32 {
+        match self.config.liquid.equation_of_state {
+            EquationOfState::Tait => self.config.liquid.speed_of_sound,
+            EquationOfState::Linear => 0.0,
+        }
+    }
+
+    fn time_step(&self) -> f32 {
+        let adaptive = match self.config.adaptive_step {
+            None => return self.config.step,
+            Some(adaptive) => adaptive,
+        };
+
+        let (max_velocity, max_acceleration, max_viscosity) = self.pool.install(|| {
+            self.particles
+                .par_iter()
+                .map(|particle| {
+                    (
+                        particle.velocity.length(),
+                        particle.acceleration.length(),
+                        particle.material.viscosity,
+                    )
+                })
+                .reduce(
+                    || (0.0f32, 0.0f32, 0.0f32),
+                    |a, b| (a.0.max(b.0), a.1.max(b.1), a.2.max(b.2)),
+                )
+        });
+
+        let max_diffusivity = self
+            .scalars
+            .iter()
+            .map(|scalar| scalar.diffusion)
+            .fold(max_viscosity, f32::max);
+
+        let radius = self.config.radius;
+        let signal_velocity = max_velocity + self.speed_of_sound();
+        let mut time_step = adaptive.max_step;
+
+        if signal_velocity > 0.0 {
+            time_step = time_step.min(adaptive.cfl * radius / signal_velocity);
+        }
+
+        if max_diffusivity > 0.0 {
+            time_step = time_step.min(adaptive.viscous * radius * radius / max_diffusivity);
+        }
+
+        if max_acceleration > 0.0 {
+            time_step = time_step.min(adaptive.force * (radius / max_acceleration).sqrt());
+        }
+
+        time_step.clamp(adaptive.min_step, adaptive.max_step)
+    }
+
+    fn find_neighbors(&mut self) {
+        if self.config.neighbor_search == NeighborSearch::Grid {
+            self.grid
+                .rebuild(self.particles.iter().map(|particle| particle.position));
+        }
+
+        let n = self.particles.len();
+        let neighbors = self.pool.install(|| {
+            (0..n)
+                .into_par_iter()
+                .map(|i| self.neighbors_of(i))
+                .collect()
+        });
+
+        self.neighbors = neighbors;
+    }
+
+    fn neighbors_of(&self, i: usize) -> Vec<usize> {
+        let position = self.particles[i].position;
+        let is_neighbor = |j: &usize| {
+            let r = (position - self.particles[*j].position).length();
+            i != *j && r <= self.config.radius
+        };
+
+        match self.config.neighbor_search {
+            NeighborSearch::Grid => {
+                let mut neighbors = self
+                    .grid
+                    .candidates(position)
+                    .filter(is_neighbor)
+                    .collect::<Vec<_>>();
+                // Saturated cells at huge positions can be visited twice.
+                neighbors.sort_unstable();
+                neighbors.dedup();
+                neighbors
+            }
+            NeighborSearch::BruteForce => (0..self.particles.len()).filter(is_neighbor).collect(),
+        }
     }
 
     fn compute_uncorrected_densities(&mut self) {
-        for i in 0..self.particles.len() {
-            let (before, nonbefore) = self.particles.split_at_mut(i);
-            let (pi, after) = nonbefore.split_first_mut().unwrap();
+        let n = self.particles.len();
+        let densities: Vec<f32> = self.pool.install(|| {
+            (0..n)
+                .into_par_iter()
+                .map(|i| self.uncorrected_density(i))
+                .collect()
+        });
 
-            pi.density = self.kernel.w0();
+        self.particles
+            .iter_mut()
+            .zip(densities)
+            .for_each(|(particle, density)| particle.density = density);
+    }
 
-            for pj in before.iter().chain(after.iter()) {
-                if pi.fluid_type != pj.fluid_type {
-                    continue;
-                }
+    fn uncorrected_density(&self, i: usize) -> f32 {
+        let pi = &self.particles[i];
+        // The Tait equation of state needs the physical self contribution, the
+        // linear models are tuned around the unscaled one.
+        let mut density = match (pi.fluid_type, self.config.liquid.equation_of_state) {
+            (FluidType::Liquid, EquationOfState::Tait) => pi.material.mass * self.kernel.w0(),
+            _ => self.kernel.w0(),
+        };
 
-                let diff = pi.position - pj.position;
-                let r = diff.length();
+        for &j in &self.neighbors[i] {
+            let pj = &self.particles[j];
 
-                if r > 0.0 && r <= self.config.radius {
-                    pi.density += self.config.mass * self.kernel.w(diff);
-                }
+            if pi.fluid_type != pj.fluid_type {
+                continue;
+            }
+
+            let diff = pi.position - pj.position;
+            let r = diff.length();
+
+            if r > 0.0 && r <= self.config.radius {
+                density += pj.material.mass * self.kernel.w(diff);
             }
         }
+
+        density
     }
 
     fn compute_densities(&mut self) {
-        for i in 0..self.particles.len() {
-            if self.particles[i].fluid_type == FluidType::Liquid {
+        let n = self.particles.len();
+        let corrections: Vec<Option<f32>> = self.pool.install(|| {
+            (0..n)
+                .into_par_iter()
+                .map(|i| self.density_correction(i))
+                .collect()
+        });
+
+        self.particles
+            .iter_mut()
+            .zip(corrections)
+            .for_each(|(particle, correction)| {
+                if let Some(correction) = correction {
+                    particle.density_correction = correction;
+                }
+            });
+    }
+
+    fn density_correction(&self, i: usize) -> Option<f32> {
+        let pi = &self.particles[i];
+
+        if pi.fluid_type == FluidType::Liquid {
+            return None;
+        }
+
+        let mut density = Vec3::ZERO;
+
+        for &j in &self.neighbors[i] {
+            let pj = &self.particles[j];
+
+            if pj.fluid_type == FluidType::Liquid {
                 continue;
             }
 
-            let (before, nonbefore) = self.particles.split_at_mut(i);
-            let (pi, after) = nonbefore.split_first_mut().unwrap();
+            let diff = pi.position - pj.position;
+            let r = diff.length();
 
-            let mut density = Vec3::ZERO;
+            if r > 0.0 && r <= self.config.radius {
+                density -= pj.material.mass / pj.density * self.kernel.density_grad_w(diff);
+            }
+        }
 
-            for pj in before.iter().chain(after.iter()) {
-                if pj.fluid_type == FluidType::Liquid {
-                    continue;
-                }
+        let v0 = density.length()
+            / self
+                .kernel
+                .density_grad_w(self.config.virtual_particle)
+                .length();
 
-                let diff = pi.position - pj.position;
-                let r = diff.length();
+        Some(pi.density * (1.0 + v0 * self.kernel.w(self.config.virtual_particle)))
+    }
 
-                if r > 0.0 && r <= self.config.radius {
-                    density -= self.config.mass / pj.density * self.kernel.poly6_grad_w(diff);
-                }
+    fn compute_normals(&mut self) {
+        if self.config.liquid.surface_tension <= 0.0 {
+            return;
+        }
+
+        let n = self.particles.len();
+        let normals: Vec<Vec3> = self
+            .pool
+            .install(|| (0..n).into_par_iter().map(|i| self.normal(i)).collect());
+
+        self.particles
+            .iter_mut()
+            .zip(normals)
+            .for_each(|(particle, normal)| particle.normal = normal);
+    }
+
+    fn normal(&self, i: usize) -> Vec3 {
+        let pi = &self.particles[i];
+
+        if pi.fluid_type != FluidType::Liquid {
+            return Vec3::ZERO;
+        }
+
+        self.liquid_neighbors(i)
+            .map(|j| {
+                let pj = &self.particles[j];
+                pj.material.mass / pj.density
+                    * self.kernel.density_grad_w(pi.position - pj.position)
+            })
+            .fold(Vec3::ZERO, |sum, gradient| sum + gradient)
+            * self.config.radius
+    }
+
+    fn compute_forces(&mut self, time_step: f32) {
+        let n = self.particles.len();
+        let forces: Vec<(Vec3, f32)> = self.pool.install(|| {
+            (0..n)
+                .into_par_iter()
+                .map(|i| self.forces(i, time_step))
+                .collect()
+        });
+
+        self.particles
+            .iter_mut()
+            .zip(forces)
+            .for_each(|(particle, (forces, temperature))| {
+                particle.forces = forces;
+                particle.temperature = temperature;
+            });
+    }
+
+    fn pressure(&self, particle: &SimulationParticle, density: f32) -> f32 {
+        let liquid = &self.config.liquid;
+        let material = &particle.material;
+
+        match (particle.fluid_type, liquid.equation_of_state) {
+            (FluidType::Liquid, EquationOfState::Tait) => {
+                let rest_density = self.liquid_rest_density(particle);
+                let stiffness =
+                    rest_density * liquid.speed_of_sound * liquid.speed_of_sound / liquid.exponent;
+
+                stiffness * ((density / rest_density).powf(liquid.exponent) - 1.0)
             }
+            _ => material.gas_constant * (density - material.rest_density),
+        }
+    }
+
+    fn liquid_rest_density(&self, particle: &SimulationParticle) -> f32 {
+        liquid_rest_density(
+            &self.config.liquid,
+            &particle.material_name,
+            &particle.material,
+        )
+    }
+
+    // Akinci et al. cohesion and curvature terms between two liquid particles.
+    fn surface_tension_acceleration(
+        &self,
+        pi: &SimulationParticle,
+        pj: &SimulationParticle,
+    ) -> Vec3 {
+        let diff = pi.position - pj.position;
+        let r = diff.length();
+        let correction = 2.0 * self.liquid_rest_density(pi) / (pi.density + pj.density);
+
+        -correction
+            * self.config.liquid.surface_tension
+            * (pj.material.mass * self.kernel.cohesion(r) * diff / r + (pi.normal - pj.normal))
+    }
+
+    fn adhesion_acceleration(&self, pi: &SimulationParticle) -> Vec3 {
+        if self.config.liquid.adhesion <= 0.0 {
+            return Vec3::ZERO;
+        }
+
+        self.boundary
+            .contacts(pi.position, self.config.radius)
+            .iter()
+            .filter(|contact| contact.surface != Surface::Ceiling)
+            .map(|contact| {
+                -self.config.liquid.adhesion
+                    * self.kernel.adhesion(contact.distance)
+                    * contact.normal
+            })
+            .fold(Vec3::ZERO, |sum, adhesion| sum + adhesion)
+    }
 
-            let v0 = density.length()
-                / self
-                    .kernel
-                    .poly6_grad_w(self.config.virtual_particle)
-                    .length();
-            pi.density_correction =
-                pi.density * (1.0 + v0 * self.kernel.w(self.config.virtual_particle));
+    fn phase_mass(&self, particle: &SimulationParticle) -> f32 {
+        match particle.fluid_type {
+            FluidType::Gaseous => particle.material.mass,
+            FluidType::Liquid => particle.material.mass * self.config.phases.density_ratio,
         }
     }
 
-    fn compute_forces(&mut self) {
-        for i in 0..self.particles.len() {
-            let (before, nonbefore) = self.particles.split_at_mut(i);
-            let (pi, after) = nonbefore.split_first_mut().unwrap();
+    // Pressure and drag exchanged across the gas-liquid interface, written so
+    // that the pair conserves momentum.
+    fn interface_acceleration(
+        &self,
+        pi: &SimulationParticle,
+        pj: &SimulationParticle,
+        pressure_i: f32,
+    ) -> Vec3 {
+        let diff = pi.position - pj.position;
+        let r = diff.length();
+
+        if r <= 0.0 || r > self.config.radius {
+            return Vec3::ZERO;
+        }
+
+        let mass_j = self.phase_mass(pj);
+        let pressure_j = self.pressure(pj, pj.density);
+
+        let pressure = -mass_j
+            * (pressure_i / (pi.density * pi.density) + pressure_j / (pj.density * pj.density))
+            * self.kernel.pressure_grad_w(diff);
+
+        let drag = self.config.phases.drag_coefficient * mass_j * 2.0 / (pi.density + pj.density)
+            * (pj.velocity - pi.velocity)
+            * self.kernel.w(diff);
+
+        pressure + drag
+    }
+
+    fn forces(&self, i: usize, time_step: f32) -> (Vec3, f32) {
+        let pi = &self.particles[i];
+
+        let mut damping = Vec3::ZERO;
+        let mut atmospheric_pressure = Vec3::ZERO;
+        let mut pressure = Vec3::ZERO;
+        let mut viscosity = Vec3::ZERO;
+        let mut temperature = 0.0f32;
 
-            let mut damping = Vec3::ZERO;
-            let mut atmospheric_pressure = Vec3::ZERO;
-            let mut pressure = Vec3::ZERO;
-            let mut viscosity = Vec3::ZERO;
-            let mut temperature = 0.0f32;
+        let pressure_i = self.pressure(pi, pi.density);
+        let pressure_k = self.pressure(pi, pi.density_correction);
 
-            for pj in before.iter().chain(after.iter()) {
-                if pi.fluid_type != pj.fluid_type {
-                    continue;
+        let mut interface = Vec3::ZERO;
+        let mut surface_tension = Vec3::ZERO;
+
+        for &j in &self.neighbors[i] {
+            let pj = &self.particles[j];
+
+            if pi.fluid_type != pj.fluid_type {
+                if self.config.phases.coupling == PhaseCoupling::Coupled {
+                    interface += self.interface_acceleration(pi, pj, pressure_i);
                 }
 
-                let pressure_i = self.config.gas_constant * (pi.density - self.config.rest_density);
-                let pressure_j = self.config.gas_constant * (pj.density - self.config.rest_density);
-                let pressure_k =
-                    self.config.gas_constant * (pi.density_correction - self.config.rest_density);
+                continue;
+            }
+
+            let pressure_j = self.pressure(pj, pj.density);
+            let mass_i = pi.material.mass;
+            let mass_j = pj.material.mass;
 
-                let diff = pi.position - pj.position;
-                let r = diff.dot(diff);
+            let diff = pi.position - pj.position;
+            let r = diff.length();
 
-                if r > 0.0 && r <= self.config.radius {
-                    match pi.fluid_type {
-                        FluidType::Gaseous => {
-                            atmospheric_pressure +=
-                                (self.config.mass / pressure_j) * self.kernel.spiky_grad_w(diff);
+            if r > 0.0 && r <= self.config.radius {
+                match pi.fluid_type {
+                    FluidType::Gaseous => {
+                        atmospheric_pressure +=
+                            (mass_j / pressure_j) * self.kernel.pressure_grad_w(diff);
 
-                            pressure -= (self.config.mass / pj.density)
+                        pressure -= (mass_j / pj.density)
+                            * ((pressure_i + pressure_j) / 2.0)
+                            * self.kernel.pressure_grad_w(diff)
+                            + (mass_i / pi.density_correction)
+                                * ((pressure_i + pressure_k) / 2.0)
+                                * self.kernel.pressure_grad_w(self.config.virtual_particle);
+
+                        viscosity += mass_j * (pj.velocity - pi.velocity) / pj.density
+                            * self.kernel.viscosity_laplacian_w(diff);
+
+                        let conductivity = (pi.material.thermal_conductivity
+                            + pj.material.thermal_conductivity)
+                            / 2.0;
+
+                        temperature += (mass_j / (pressure_i * pressure_j))
+                            * conductivity
+                            * (pi.temperature - pj.temperature)
+                            * (diff.dot(self.kernel.pressure_grad_w(diff))
+                                / (diff.dot(diff) + self.config.small_positive));
+                    }
+                    FluidType::Liquid => {
+                        if self.config.liquid.pressure_solver == PressureSolver::Explicit {
+                            pressure -= (mass_j / pj.density)
                                 * ((pressure_i + pressure_j) / 2.0)
-                                * self.kernel.spiky_grad_w(diff)
-                                + (self.config.mass / pi.density_correction)
-                                    * ((pressure_i + pressure_k) / 2.0)
-                                    * self.kernel.spiky_grad_w(self.config.virtual_particle);
-
-                            viscosity += self.config.mass * (pj.velocity - pi.velocity)
-                                / pj.density
-                                * self.kernel.viscosity_laplacian_w(diff);
-
-                            temperature += (self.config.mass / (pressure_i * pressure_j))
-                                * self.config.thermal_conductivity
-                                * (pi.temperature - pj.temperature)
-                                * (diff.dot(self.kernel.spiky_grad_w(diff))
-                                    / (diff.dot(diff) + self.config.small_positive));
+                                * self.kernel.pressure_grad_w(diff);
                         }
-                        FluidType::Liquid => {
-                            pressure -= (self.config.mass / pj.density)
-                                * ((pressure_i + pressure_j) / 2.0)
-                                * self.kernel.spiky_grad_w(diff);
 
-                            viscosity += self.config.mass * (pj.velocity - pi.velocity)
-                                / pj.density
-                                * self.kernel.viscosity_laplacian_w(diff);
+                        viscosity += mass_j * (pj.velocity - pi.velocity) / pj.density
+                            * self.kernel.viscosity_laplacian_w(diff);
+
+                        if self.config.liquid.surface_tension > 0.0 {
+                            surface_tension += self.surface_tension_acceleration(pi, pj);
                         }
                     }
                 }
             }
+        }
 
-            viscosity *= self.config.viscosity;
-
-            match pi.fluid_type {
-                FluidType::Gaseous => {
-                    if atmospheric_pressure.length() > self.config.damping_threshold {
-                        temperature -= pi.temperature / self.config.radiation_half_life;
-                        damping = -self.config.damping_coefficient * pi.velocity;
-                    }
+        viscosity *= pi.material.viscosity;
 
-                    pi.temperature += temperature;
+        let boundary = match self.config.boundary.handling {
+            BoundaryHandling::Solid => {
+                self.boundary
+                    .acceleration(pi.position, pi.velocity, self.config.radius)
+            }
+            BoundaryHandling::Delete => Vec3::ZERO,
+        };
 
-                    let buoyancy = self.config.buoyancy_coefficient
-                        * pi.temperature
-                        * self.config.buoyancy_direction;
+        let heat = self
+            .boundary
+            .heat_transfer(pi.position, pi.temperature, self.config.radius)
+            * time_step;
 
-                    pi.forces = (pressure + 1.0 * atmospheric_pressure)
-                        + viscosity
-                        + pi.density * (self.config.gravity + buoyancy + damping);
-                }
-                FluidType::Liquid => {
-                    pi.forces = pressure + viscosity + pi.density * self.config.gravity;
+        match pi.fluid_type {
+            FluidType::Gaseous => {
+                if atmospheric_pressure.length() > self.config.damping_threshold {
+                    temperature -= (pi.temperature - self.config.ambient.temperature)
+                        / self.config.radiation_half_life;
+                    damping = -self.config.damping_coefficient * pi.velocity;
                 }
+
+                let temperature = pi.temperature + temperature + heat;
+
+                // Boussinesq approximation: gravity is offset by the displaced
+                // ambient air and temperature only acts through its difference
+                // to the ambient temperature.
+                let rest_density = pi.material.rest_density;
+                let gravity = self.config.gravity * (rest_density - self.config.ambient_density())
+                    / rest_density;
+                let buoyancy = self.config.buoyancy_coefficient
+                    * (temperature - self.config.ambient.temperature)
+                    * self.config.buoyancy_direction;
+
+                let airflow = self.airflow.acceleration(pi.position, pi.velocity);
+
+                let forces = (pressure + 1.0 * atmospheric_pressure)
+                    + viscosity
+                    + pi.density * (gravity + buoyancy + damping + interface + boundary + airflow);
+
+                (forces, temperature)
+            }
+            FluidType::Liquid => {
+                let adhesion = self.adhesion_acceleration(pi);
+                let forces = pressure
+                    + viscosity
+                    + pi.density
+                        * (self.config.gravity + interface + surface_tension + adhesion + boundary);
+
+                (forces, pi.temperature + heat)
             }
         }
     }
 
-    fn integrate(&mut self, time_step: f32) {
-        for (i, particle) in self.particles.iter_mut().enumerate() {
-            let prev_acceleration = particle.acceleration;
-            let prev_velocity = particle.velocity;
+    fn transport_scalars(&mut self, time_step: f32) {
+        if self.scalars.is_empty() {
+            return;
+        }
+
+        let n = self.particles.len();
+        let scalars: Vec<Vec<f32>> = self.pool.install(|| {
+            (0..n)
+                .into_par_iter()
+                .map(|i| self.scalars(i, time_step))
+                .collect()
+        });
+
+        self.particles
+            .iter_mut()
+            .zip(scalars)
+            .for_each(|(particle, scalars)| particle.scalars = scalars);
+    }
+
+    // Diffusion through the SPH Laplacian followed by exponential decay.
+    // Brookshaw's Laplacian only needs the kernel gradient, so diffusion stays
+    // forward whichever kernels are configured.
+    fn scalars(&self, i: usize, time_step: f32) -> Vec<f32> {
+        let pi = &self.particles[i];
+        let epsilon = 0.01 * self.config.radius * self.config.radius;
+
+        self.scalars
+            .iter()
+            .enumerate()
+            .map(|(k, scalar)| {
+                let mut laplacian = 0.0f32;
+
+                for &j in &self.neighbors[i] {
+                    let pj = &self.particles[j];
+                    let diff = pi.position - pj.position;
+                    let r = diff.length();
+
+                    if r > 0.0 && r <= self.config.radius {
+                        laplacian += 2.0 * pj.material.mass / pj.density
+                            * (pi.scalars[k] - pj.scalars[k])
+                            * diff.dot(self.kernel.density_grad_w(diff))
+                            / (r * r + epsilon);
+                    }
+                }
+
+                let concentration = pi.scalars[k] + scalar.diffusion * laplacian * time_step;
+
+                concentration * (-scalar.decay * time_step).exp()
+            })
+            .collect()
+    }
+
+    // PCISPH: iteratively corrects liquid pressures until the predicted density
+    // error drops below the configured tolerance.
+    fn solve_pressure(&mut self, time_step: f32) {
+        if self.config.liquid.pressure_solver != PressureSolver::Pcisph
+            || !self
+                .particles
+                .iter()
+                .any(|p| p.fluid_type == FluidType::Liquid)
+        {
+            self.solver_stats = None;
+            return;
+        }
+
+        let liquid = self.config.liquid;
+        let n = self.particles.len();
+
+        let deltas: Vec<f32> = self.pool.install(|| {
+            (0..n)
+                .into_par_iter()
+                .map(|i| self.pressure_scaling(i, time_step))
+                .collect()
+        });
+
+        let mut pressures = vec![0.0f32; n];
+        let mut accelerations = vec![Vec3::ZERO; n];
+        let mut iterations = 0;
+        let mut residual = f32::INFINITY;
+
+        while iterations < liquid.max_iterations
+            && (iterations < liquid.min_iterations || residual > liquid.tolerance)
+        {
+            let predicted: Vec<Vec3> = self.pool.install(|| {
+                self.particles
+                    .par_iter()
+                    .zip(accelerations.par_iter())
+                    .map(|(particle, acceleration)| match particle.fluid_type {
+                        FluidType::Liquid => {
+                            let velocity = particle.velocity
+                                + (particle.forces / particle.density + *acceleration) * time_step;
+                            particle.position + velocity * time_step
+                        }
+                        FluidType::Gaseous => particle.position,
+                    })
+                    .collect()
+            });
+
+            let errors: Vec<f32> = self.pool.install(|| {
+                (0..n)
+                    .into_par_iter()
+                    .map(|i| {
+                        let pi = &self.particles[i];
+
+                        match pi.fluid_type {
+                            FluidType::Liquid => {
+                                self.predicted_density(i, &predicted) - self.liquid_rest_density(pi)
+                            }
+                            FluidType::Gaseous => 0.0,
+                        }
+                    })
+                    .collect()
+            });
+
+            pressures
+                .iter_mut()
+                .zip(errors.iter().zip(deltas.iter()))
+                .for_each(|(pressure, (error, delta))| {
+                    *pressure = (*pressure + delta * error).max(0.0);
+                });
+
+            residual = self
+                .particles
+                .iter()
+                .zip(errors.iter())
+                .map(|(particle, error)| error / self.liquid_rest_density(particle))
+                .fold(0.0f32, f32::max);
+
+            accelerations = self.pool.install(|| {
+                (0..n)
+                    .into_par_iter()
+                    .map(|i| self.pressure_acceleration(i, &pressures))
+                    .collect()
+            });
+
+            iterations += 1;
+        }
+
+        self.particles
+            .iter_mut()
+            .zip(accelerations)
+            .for_each(|(particle, acceleration)| {
+                particle.forces += particle.density * acceleration;
+            });
+
+        self.solver_stats = Some(SolverStats {
+            iterations,
+            residual,
+        });
+    }
+
+    fn liquid_neighbors(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        self.neighbors[i]
+            .iter()
+            .copied()
+            .filter(|j| self.particles[*j].fluid_type == FluidType::Liquid)
+    }
+
+    fn pressure_scaling(&self, i: usize, time_step: f32) -> f32 {
+        let pi = &self.particles[i];
+
+        if pi.fluid_type != FluidType::Liquid {
+            return 0.0;
+        }
+
+        let (sum, sum_sqr) =
+            self.liquid_neighbors(i)
+                .fold((Vec3::ZERO, 0.0f32), |(sum, sum_sqr), j| {
+                    let grad = self
+                        .kernel
+                        .pressure_grad_w(pi.position - self.particles[j].position);
+                    (sum + grad, sum_sqr + grad.dot(grad))
+                });
+
+        let beta = 2.0 * (time_step * pi.material.mass / self.liquid_rest_density(pi)).powf(2.0);
+        let denominator = beta * (sum.dot(sum) + sum_sqr);
+
+        if denominator > f32::EPSILON {
+            1.0 / denominator
+        } else {
+            0.0
+        }
+    }
+
+    fn predicted_density(&self, i: usize, predicted: &[Vec3]) -> f32 {
+        self.liquid_neighbors(i)
+            .map(|j| self.particles[j].material.mass * self.kernel.w(predicted[i] - predicted[j]))
+            .sum::<f32>()
+            + self.particles[i].material.mass * self.kernel.w0()
+    }
+
+    fn pressure_acceleration(&self, i: usize, pressures: &[f32]) -> Vec3 {
+        let pi = &self.particles[i];
+
+        if pi.fluid_type != FluidType::Liquid {
+            return Vec3::ZERO;
+        }
+
+        let rest_density = self.liquid_rest_density(pi);
+        let rest_density_sqr = rest_density * rest_density;
+
+        self.liquid_neighbors(i)
+            .map(|j| {
+                let pj = &self.particles[j];
+
+                -pj.material.mass * (pressures[i] + pressures[j]) / rest_density_sqr
+                    * self.kernel.pressure_grad_w(pi.position - pj.position)
+            })
+            .fold(Vec3::ZERO, |sum, acceleration| sum + acceleration)
+    }
+
+    fn predict(&mut self, time_step: f32) {
+        let integrator = &self.integrator;
+
+        self.pool.install(|| {
+            self.particles
+                .par_iter_mut()
+                .for_each(|particle| integrator.predict(particle, time_step))
+        });
+    }
+
+    fn correct(&mut self, time_step: f32) {
+        let integrator = &self.integrator;
+        let boundary = &self.boundary;
+        let solid = self.config.boundary.handling == BoundaryHandling::Solid;
+
+        self.pool.install(|| {
+            self.particles.par_iter_mut().for_each(|particle| {
+                particle.acceleration = particle.forces / particle.density;
+                integrator.correct(particle, time_step);
+
+                if solid {
+                    boundary.collide(&mut particle.position, &mut particle.velocity);
+                }
+            })
+        });
+
+        self.particles
+            .iter()
+            .zip(self.instances.iter_mut())
+            .for_each(|(particle, instance)| *instance = particle.instance());
+    }
+}
+
+// Named materials keep their own rest density, the liquid section only
+// replaces the one of the material in the simulation section.
+fn liquid_rest_density(liquid: &LiquidConfig, name: &str, material: &Material) -> f32 {
+    match name {
+        DEFAULT_MATERIAL => liquid.rest_density.unwrap_or(material.rest_density),
+        _ => material.rest_density,
+    }
+}
 
-            particle.acceleration = particle.forces / particle.density;
-            particle.velocity +=
-                (prev_acceleration + particle.acceleration) / 2.0 * time_step * time_step;
-            particle.position +=
-                prev_velocity * time_step + prev_acceleration / 2.0 * time_step * time_step;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha12Rng;
 
-            self.instances[i].position = particle.position;
+    const CONFIG: &str = "
+environment: |
+  #####
+  #...#
+  #...#
+  #####
+actuators: {}
+sensors: {}
+simulation:
+  step: 0.001
+  radius: 0.1
+  mass: 0.02
+  gas_constant: 3.0
+  rest_density: 1.0
+  thermal_conductivity: 0.598
+  small_positive: 0.0001
+  viscosity: 0.01
+  damping_coefficient: 100.0
+  damping_threshold: 1.0
+  radiation_half_life: 1000.0
+  buoyancy_coefficient: 0.8
+  buoyancy_direction: [0.0, 1.0, 0.0]
+  gravity: [0.0, -9.82, 0.0]
+  virtual_particle: [0.0, 0.01828, 0.0]
+  threads: 1
+";
+
+    fn sph() -> SPH {
+        let config: Config = serde_yaml::from_str(CONFIG).expect("Could not read config");
+        let world_map = WorldMap::new(&config);
+
+        SPH::new(&config, &world_map)
+    }
+
+    fn particle(sph: &SPH, position: Vec3) -> SimulationParticle {
+        SimulationParticle::new(
+            position,
+            Vec3::ZERO,
+            25.0,
+            FluidType::Gaseous,
+            0.02,
+            Vec3::ONE,
+            sph.config.default_material(),
+        )
+    }
+
+    #[test]
+    fn removal_keeps_moved_particle_reachable() {
+        let mut sph = sph();
+        let ids: Vec<ParticleId> = (0..3)
+            .map(|i| sph.add_particle(particle(&sph, Vec3::splat(i as f32))))
+            .collect();
+
+        let removed = sph.remove_particle(ids[0]).expect("Particle not found");
+
+        assert_eq!(removed.get_id(), ids[0]);
+        assert!(sph.get_particle(ids[0]).is_none());
+        assert!(sph.remove_particle(ids[0]).is_none());
+        assert_eq!(sph.get_particles()[0].get_id(), ids[2]);
+        assert_eq!(sph.get_particle(ids[2]).unwrap().position, Vec3::splat(2.0));
+        assert_eq!(sph.get_particle(ids[1]).unwrap().position, Vec3::splat(1.0));
+    }
+
+    #[test]
+    fn grid_finds_brute_force_neighbors() {
+        let mut sph = sph();
+        let mut rng = ChaCha12Rng::seed_from_u64(0);
+
+        for _ in 0..500 {
+            let position = Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 0.5;
+            sph.add_particle(particle(&sph, position));
         }
+        sph.add_particle(particle(&sph, Vec3::splat(1e30)));
+        sph.add_particle(particle(&sph, Vec3::splat(1e30)));
+
+        sph.config.neighbor_search = NeighborSearch::BruteForce;
+        sph.find_neighbors();
+        let brute_force = sph.neighbors.clone();
+
+        sph.config.neighbor_search = NeighborSearch::Grid;
+        sph.find_neighbors();
+
+        assert_eq!(sph.neighbors, brute_force);
     }
 }
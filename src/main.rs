@@ -8,6 +8,9 @@ use glam::Vec3;
 use winit::event::KeyboardInput;
 
 use crate::app::App;
+use crate::cfd::checkpoint::{Checkpoint, Checkpointer};
+use crate::cfd::report::Reporter;
+use crate::cfd::sph::particle_id::ParticleId;
 use crate::cfd::sph::simulation::{SimulationParticle, SPH};
 use crate::gfx::buffer::VertexBuffer;
 use crate::gfx::camera::controller::FirstPersonController;
@@ -38,6 +41,9 @@ struct FluidSense {
     particle: Particle,
     particle_instance_buffer: VertexBuffer,
     sph: SPH,
+    checkpointer: Option<Checkpointer>,
+    reporter: Option<Reporter>,
+    scene_outdated: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -46,6 +52,58 @@ struct Args {
     config: String,
     #[arg(long, default_value_t = false)]
     headless: bool,
+    #[arg(long)]
+    threads: Option<usize>,
+    #[arg(long)]
+    seed: Option<u64>,
+    #[arg(long)]
+    resume: Option<String>,
+    #[arg(long)]
+    checkpoint_interval: Option<f32>,
+    #[arg(long, default_value = "checkpoints")]
+    checkpoint_dir: String,
+    #[arg(long)]
+    report_interval: Option<f32>,
+    #[arg(long)]
+    track: Vec<ParticleId>,
+    #[arg(long, default_value_t = false)]
+    check_kernels: bool,
+}
+
+impl Args {
+    fn load_config(&self) -> cfd::config::Config {
+        let mut config = cfd::config::Config::new(&self.config);
+
+        if let Some(threads) = self.threads {
+            config.set_threads(threads);
+        }
+
+        if let Some(seed) = self.seed {
+            config.set_seed(seed);
+        }
+
+        config.ensure_seed();
+
+        config
+    }
+
+    fn resume(&self, sph: &mut SPH, world_map: &mut WorldMap) {
+        if let Some(filename) = &self.resume {
+            Checkpoint::load(filename).restore(sph, world_map);
+            log::info!("Resumed {} at {}s", filename, sph.get_time());
+        }
+    }
+
+    fn checkpointer(&self, sph: &SPH) -> Option<Checkpointer> {
+        self.checkpoint_interval
+            .map(|interval| Checkpointer::new(&self.checkpoint_dir, interval, sph.get_time()))
+    }
+
+    fn reporter(&self, sph: &SPH) -> Option<Reporter> {
+        self.report_interval.map(|interval| {
+            Reporter::new(interval, sph.get_time()).with_tracked(self.track.clone())
+        })
+    }
 }
 
 impl App for FluidSense {
@@ -53,8 +111,12 @@ impl App for FluidSense {
         let args = Args::parse();
         let phong_pipeline = Pipeline::phong(renderer);
         let particle_pipeline = Pipeline::particle(renderer);
-        let config = cfd::config::Config::new(&args.config);
+        let config = args.load_config();
         let mut world_map = WorldMap::new(&config);
+        let mut sph = SPH::new(&config, &world_map);
+        args.resume(&mut sph, &mut world_map);
+        let checkpointer = args.checkpointer(&sph);
+        let reporter = args.reporter(&sph);
         let scene = world_map.build_scene(renderer, &phong_pipeline);
         let (x, z) = scene.user_position();
         let projection = Perspective::new(45.0, renderer.get_aspect_ratio(), 0.1, 1000.0);
@@ -69,7 +131,6 @@ impl App for FluidSense {
         let camera_controller = FirstPersonController::new(0.0, 90.0, 4.0, 0.1);
         let light = Light::new(&renderer, &phong_pipeline, camera.position(), Vec3::ONE);
         let particle = Particle::new(renderer);
-        let sph = SPH::new(&config);
         let particle_instance_buffer = VertexBuffer::new(renderer, sph.get_particle_instances());
 
         Self {
@@ -83,6 +144,9 @@ impl App for FluidSense {
             particle,
             particle_instance_buffer,
             sph,
+            checkpointer,
+            reporter,
+            scene_outdated: false,
         }
     }
 
@@ -97,28 +161,12 @@ impl App for FluidSense {
     fn update(&mut self, dt: Duration) {
         self.camera_controller.update(&mut self.camera, dt);
         self.light.set_position(self.camera.position());
-        self.sph.step(0.001);
-        self.sph.check_particles(&self.world_map);
-
-        self.world_map
-            .get_actuators()
-            .iter_mut()
-            .for_each(|(_, actuator)| match actuator.emit_particle(&dt) {
-                None => {}
-                Some(particle) => {
-                    self.sph.add_particle(particle);
-                }
-            });
-
-        self.sph.get_particles().iter().for_each(|particle| {
-            match self.world_map.get_device_in_position(particle.position) {
-                Some(label) => match self.world_map.get_sensor_by_label(&label) {
-                    Some(sensor) => sensor.inspect_particle(particle),
-                    None => {}
-                },
-                None => {}
-            }
-        });
+        self.scene_outdated |= advance(
+            &mut self.sph,
+            &mut self.world_map,
+            &mut self.checkpointer,
+            &mut self.reporter,
+        );
     }
 
     fn resize(&mut self, width: u32, height: u32) {
@@ -126,6 +174,11 @@ impl App for FluidSense {
     }
 
     fn render<'a>(&'a mut self, renderer: &Renderer, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.scene_outdated {
+            self.world_map.update_scene(renderer, &mut self.scene);
+            self.scene_outdated = false;
+        }
+
         render_pass.set_pipeline(&self.phong_pipeline);
         self.camera.update(&renderer, render_pass);
         self.light.update(&renderer, render_pass);
@@ -139,46 +192,112 @@ impl App for FluidSense {
 }
 
 fn run_headless() {
+    env_logger::init();
+
     let args = Args::parse();
-    let config = cfd::config::Config::new(&args.config);
-    let mut sph = SPH::new(&config);
+    let config = args.load_config();
     let mut world_map = WorldMap::new(&config);
+    let mut sph = SPH::new(&config, &world_map);
+    args.resume(&mut sph, &mut world_map);
+    let mut checkpointer = args.checkpointer(&sph);
+    let mut reporter = args.reporter(&sph);
 
     loop {
-        sph.step(0.001);
-        sph.check_particles(&world_map);
+        advance(&mut sph, &mut world_map, &mut checkpointer, &mut reporter);
+    }
+}
 
-        let dt = Duration::from_secs_f64(0.016);
+// Advances the simulation by one step and everything driven by it. Returns
+// whether the walls changed and the scene has to be rebuilt.
+fn advance(
+    sph: &mut SPH,
+    world_map: &mut WorldMap,
+    checkpointer: &mut Option<Checkpointer>,
+    reporter: &mut Option<Reporter>,
+) -> bool {
+    let time_step = sph.step();
+    let dt = Duration::from_secs_f32(time_step);
+    sph.absorb_particles(world_map, time_step);
+    sph.check_particles(world_map);
 
-        world_map
-            .get_actuators()
-            .iter_mut()
-            .for_each(|(_, actuator)| match actuator.emit_particle(&dt) {
-                None => {}
-                Some(particle) => {
-                    sph.add_particle(particle);
-                }
-            });
-
-        sph.get_particles().iter().for_each(|particle| {
-            match world_map.get_device_in_position(particle.position) {
-                Some(label) => match world_map.get_sensor_by_label(&label) {
-                    Some(sensor) => sensor.inspect_particle(particle),
-                    None => {}
-                },
-                None => {}
-            }
+    let walls_changed = world_map.update_doors(sph.get_time());
+
+    if walls_changed {
+        sph.rebuild_boundaries(world_map);
+    }
+
+    if let Some(stats) = sph.get_solver_stats() {
+        log::debug!(
+            "Pressure solver at {}s: {} iterations, residual {}",
+            sph.get_time(),
+            stats.iterations,
+            stats.residual
+        );
+    }
+
+    world_map.update_timeline(sph.get_time());
+    world_map
+        .get_actuators()
+        .iter_mut()
+        .for_each(|(label, actuator)| {
+            actuator
+                .emit_particles(&dt)
+                .into_iter()
+                .for_each(|particle| {
+                    sph.emit_particle(particle.with_source(*label));
+                })
         });
+
+    sph.get_particles().iter().for_each(|particle| {
+        match world_map.get_device_in_position(particle.position) {
+            Some(label) => match world_map.get_sensor_by_label(&label) {
+                Some(sensor) => sensor.inspect_particle(sph.get_time(), particle),
+                None => {}
+            },
+            None => {}
+        }
+    });
+
+    if let Some(checkpointer) = checkpointer {
+        checkpointer.update(sph, world_map);
+    }
+
+    if let Some(reporter) = reporter {
+        reporter.update(sph, world_map);
+    }
+
+    walls_changed
+}
+
+fn check_kernels() {
+    let args = Args::parse();
+    let config = args.load_config();
+    let checks = cfd::sph::kernel::check_kernels(config.get_simulation_config().radius);
+
+    checks.iter().for_each(|check| {
+        println!(
+            "{:?}: integral = {:.5}, gradient error = {:.2e}, laplacian error = {:.2e} [{}]",
+            check.kind,
+            check.integral,
+            check.gradient_error,
+            check.laplacian_error,
+            if check.passed() { "ok" } else { "FAILED" }
+        );
+    });
+
+    if !checks.iter().all(|check| check.passed()) {
+        std::process::exit(1);
     }
 }
 
 fn main() {
     let args = Args::parse();
 
-    if args.headless {
+    if args.check_kernels {
+        check_kernels();
+    } else if args.headless {
         run_headless();
     } else {
         pollster::block_on(app::run::<FluidSense>());
     }
 }
-
@@ -53,6 +53,16 @@ impl Scene {
         }
     }
 
+    pub fn update_instances(
+        &mut self,
+        renderer: &Renderer,
+        floor_instances: &[InstanceVertex],
+        wall_instances: &[InstanceVertex],
+    ) {
+        self.floor_instance_buffer.update(renderer, floor_instances);
+        self.wall_instance_buffer.update(renderer, wall_instances);
+    }
+
     pub fn user_position(&self) -> (f32, f32) {
         self.user_position
     }
@@ -1,12 +1,15 @@
-use crate::cfd::config::{ActuatorConfig, Config, FluidType, ParticleConfig, SensorConfig};
+use crate::cfd::config::{
+    ActuatorConfig, Config, Decay, DoorConfig, DoorEvent, EmissionPattern, FluidType, Keyframe,
+    Material, ParticleConfig, PulseConfig, SensorConfig, SinkConfig, TimelineConfig, VentConfig,
+};
 use crate::gfx::vertex::InstanceVertex;
 use crate::scene::object::Transform;
 use crate::{Renderer, Scene, SimulationParticle};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use glam::{EulerRot, Quat, Vec3};
-use rand::rngs::ThreadRng;
 use rand::Rng;
+use rand_chacha::ChaCha12Rng;
 use serde::{Deserialize, Serialize};
 
 use std::time::Duration;
@@ -26,45 +29,211 @@ impl ActuatorParticle {
     }
 }
 
+// Values the timeline falls back to before the first keyframe of a property.
+#[derive(Debug, Clone, Copy)]
+struct Setpoints {
+    enabled: bool,
+    interval: f32,
+    temperature: f32,
+    velocity: f32,
+}
+
+// Value of the last keyframe at or before `time`.
+fn step<T: Copy>(keyframes: &[Keyframe<T>], time: f32) -> Option<T> {
+    keyframes
+        .iter()
+        .take_while(|keyframe| keyframe.time <= time)
+        .last()
+        .map(|keyframe| keyframe.value)
+}
+
+// Linear interpolation between the keyframes around `time`, holding the last
+// value after the final keyframe.
+fn interpolate(keyframes: &[Keyframe<f32>], time: f32) -> Option<f32> {
+    let next = keyframes.iter().position(|keyframe| keyframe.time > time);
+
+    match next {
+        Some(0) => None,
+        Some(next) => {
+            let (a, b) = (keyframes[next - 1], keyframes[next]);
+            let t = (time - a.time) / (b.time - a.time);
+            Some(a.value + (b.value - a.value) * t)
+        }
+        None => keyframes.last().map(|keyframe| keyframe.value),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ActuatorState {
+    dt: f32,
+    time: f32,
+    rng: ChaCha12Rng,
+}
+
 #[derive(Debug)]
 pub struct Actuator {
-    rng: ThreadRng,
+    rng: ChaCha12Rng,
     position: Vec3,
     direction: Vec3,
     initial_velocity: f32,
-    temperature: Option<f32>,
+    velocity_jitter: f32,
+    pattern: EmissionPattern,
+    temperature: f32,
     range: Vec3,
     fluid_type: FluidType,
     interval: f32,
+    burst: usize,
+    pulse: Option<PulseConfig>,
     dt: f32,
+    time: f32,
     particle: ActuatorParticle,
+    material: Material,
+    material_name: String,
+    concentrations: Vec<f32>,
+    lifetime: Option<f32>,
+    decay: Decay,
+    enabled: bool,
+    setpoints: Setpoints,
+    timeline: TimelineConfig,
 }
 
 impl Actuator {
-    pub fn new(x: f32, z: f32, config: &ActuatorConfig) -> Self {
+    pub fn new(
+        x: f32,
+        z: f32,
+        config: &ActuatorConfig,
+        material: Material,
+        concentrations: Vec<f32>,
+        ambient_temperature: f32,
+        rng: ChaCha12Rng,
+    ) -> Self {
+        if config.decay == Decay::Linear && config.lifetime.is_none() {
+            log::warn!("Linear decay has no effect on actuators without a lifetime");
+        }
+
+        if let Some(lifetime) = config.lifetime.filter(|lifetime| *lifetime <= 0.0) {
+            panic!("Actuator lifetime must be positive, got {}", lifetime);
+        }
+
+        if let Decay::Exponential { half_life } = config.decay {
+            if half_life <= 0.0 {
+                panic!(
+                    "Exponential decay half life must be positive, got {}",
+                    half_life
+                );
+            }
+        }
+
+        let direction = config.direction.normalize_or_zero();
+
+        if direction == Vec3::ZERO {
+            log::warn!("Actuator direction is zero, particles are emitted at rest");
+        }
+
+        let temperature = config.temperature.unwrap_or(ambient_temperature);
+
         Self {
-            rng: rand::thread_rng(),
+            rng,
             position: Vec3::new(x, config.height, z),
-            direction: config.direction,
+            direction,
             initial_velocity: config.initial_velocity,
-            temperature: config.temperature,
+            velocity_jitter: config.velocity_jitter,
+            pattern: config.pattern,
+            temperature,
             range: config.range,
             fluid_type: config.fluid_type,
             interval: config.interval,
+            burst: config.burst,
+            pulse: config.pulse,
             dt: 0.0,
+            time: 0.0,
             particle: ActuatorParticle::new(&config.particle),
+            material,
+            material_name: String::new(),
+            concentrations,
+            lifetime: config.lifetime,
+            decay: config.decay,
+            enabled: config.enabled,
+            setpoints: Setpoints {
+                enabled: config.enabled,
+                interval: config.interval,
+                temperature,
+                velocity: config.initial_velocity,
+            },
+            timeline: TimelineConfig::default(),
+        }
+    }
+
+    pub fn with_material_name(mut self, material_name: String) -> Self {
+        self.material_name = material_name;
+        self
+    }
+
+    pub fn with_timeline(mut self, timeline: &TimelineConfig) -> Self {
+        let mut timeline = timeline.clone();
+        timeline.enabled.sort_by(|a, b| a.time.total_cmp(&b.time));
+        timeline.interval.sort_by(|a, b| a.time.total_cmp(&b.time));
+        timeline
+            .temperature
+            .sort_by(|a, b| a.time.total_cmp(&b.time));
+        timeline.velocity.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        self.timeline = timeline;
+        self
+    }
+
+    // Sets the keyframed properties to their values at the simulation `time`.
+    pub fn update(&mut self, time: f32) {
+        let timeline = &self.timeline;
+        let setpoints = self.setpoints;
+
+        self.enabled = step(&timeline.enabled, time).unwrap_or(setpoints.enabled);
+        self.interval = interpolate(&timeline.interval, time).unwrap_or(setpoints.interval);
+        self.temperature =
+            interpolate(&timeline.temperature, time).unwrap_or(setpoints.temperature);
+        self.initial_velocity = interpolate(&timeline.velocity, time).unwrap_or(setpoints.velocity);
+    }
+
+    pub fn save_state(&self) -> ActuatorState {
+        ActuatorState {
+            dt: self.dt,
+            time: self.time,
+            rng: self.rng.clone(),
         }
     }
 
-    pub fn emit_particle(&mut self, dt: &Duration) -> Option<SimulationParticle> {
+    pub fn restore_state(&mut self, state: ActuatorState) {
+        self.dt = state.dt;
+        self.time = state.time;
+        self.rng = state.rng;
+    }
+
+    pub fn emit_particles(&mut self, dt: &Duration) -> Vec<SimulationParticle> {
         self.dt += dt.as_secs_f32();
+        self.time += dt.as_secs_f32();
+
+        // A disabled actuator starts a fresh interval once it is enabled.
+        if !self.enabled {
+            self.dt = 0.0;
+            return Vec::new();
+        }
 
         if self.dt < self.interval {
-            return None;
+            return Vec::new();
         }
 
         self.dt = 0.0;
 
+        if let Some(pulse) = self.pulse {
+            if self.time % pulse.period >= pulse.duty * pulse.period {
+                return Vec::new();
+            }
+        }
+
+        (0..self.burst).map(|_| self.emit_particle()).collect()
+    }
+
+    fn emit_particle(&mut self) -> SimulationParticle {
         let jitter_x: f32 = self.rng.gen::<f32>() * self.range.x;
         let jitter_y: f32 = self.rng.gen::<f32>() * self.range.y;
         let jitter_z: f32 = self.rng.gen::<f32>() * self.range.z;
@@ -75,23 +244,46 @@ impl Actuator {
             self.position.z + jitter_z,
         );
 
-        let velocity = self.direction * self.initial_velocity;
-
-        let temperature = match self.temperature {
-            None => 25.0,
-            Some(temperature) => temperature,
-        };
+        let speed = self.initial_velocity + self.velocity_jitter * self.rng.gen_range(-1.0..=1.0);
+        let velocity = self.launch_direction() * speed.max(0.0);
 
-        let particle = SimulationParticle::new(
+        SimulationParticle::new(
             position,
             velocity,
-            temperature,
+            self.temperature,
             self.fluid_type.clone(),
             self.particle.size,
             self.particle.color,
-        );
+            self.material,
+        )
+        .with_scalars(self.concentrations.clone())
+        .with_material_name(self.material_name.clone())
+        .with_lifetime(self.lifetime, self.decay)
+    }
+
+    fn launch_direction(&mut self) -> Vec3 {
+        match self.pattern {
+            EmissionPattern::Jet => self.direction,
+            // Uniform over the spherical cap around the direction.
+            EmissionPattern::Cone { spread } => {
+                let cos_spread = spread.to_radians().cos();
+                let cos_angle = 1.0 - self.rng.gen::<f32>() * (1.0 - cos_spread);
+                let sin_angle = (1.0 - cos_angle * cos_angle).max(0.0).sqrt();
+                let azimuth = self.rng.gen::<f32>() * std::f32::consts::TAU;
+                let (a, b) = self.direction.any_orthonormal_pair();
+
+                self.direction * cos_angle + (a * azimuth.cos() + b * azimuth.sin()) * sin_angle
+            }
+            EmissionPattern::Fan { spread } => {
+                let side = match self.direction.cross(Vec3::Y).normalize_or_zero() {
+                    Vec3::ZERO => self.direction.any_orthonormal_vector(),
+                    side => side,
+                };
+                let angle = self.rng.gen_range(-1.0..=1.0) * spread.to_radians();
 
-        Some(particle)
+                self.direction * angle.cos() + side * angle.sin()
+            }
+        }
     }
 }
 
@@ -101,26 +293,195 @@ pub struct Sensor {
     position: Vec3,
     range: Vec3,
     output: Option<String>,
+    scalars: Vec<String>,
 }
 
 impl Sensor {
-    pub fn new(label: char, x: f32, z: f32, config: &SensorConfig) -> Self {
+    pub fn new(label: char, x: f32, z: f32, config: &SensorConfig, scalars: Vec<String>) -> Self {
         Self {
             label,
             position: Vec3::new(x, config.height, z),
             range: config.range,
             output: config.output.clone(),
+            scalars,
         }
     }
 
-    pub fn inspect_particle(&self, particle: &SimulationParticle) {
+    pub fn inspect_particle(&self, time: f32, particle: &SimulationParticle) {
+        let concentrations: BTreeMap<&String, f32> = self
+            .scalars
+            .iter()
+            .zip(particle.get_scalars().iter().copied())
+            .collect();
+
         println!(
-            "Sensor({}, {}, {}, {:?}) detected particle: {:?}",
-            self.label, self.position, self.range, self.output, particle
+            "Sensor({}, {}, {}, {:?}) detected particle {} at {}s: {:?} {:?}",
+            self.label,
+            self.position,
+            self.range,
+            self.output,
+            particle.get_id(),
+            time,
+            concentrations,
+            particle
+        );
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct Extraction {
+    pub count: usize,
+    pub mass: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SinkState {
+    allowance: f32,
+    extracted: BTreeMap<String, Extraction>,
+}
+
+#[derive(Debug)]
+pub struct Sink {
+    label: char,
+    position: Vec3,
+    range: Vec3,
+    rate: Option<f32>,
+    allowance: f32,
+    extracted: BTreeMap<String, Extraction>,
+}
+
+impl Sink {
+    pub fn new(label: char, x: f32, z: f32, config: &SinkConfig) -> Self {
+        Self {
+            label,
+            position: Vec3::new(x, config.height, z),
+            range: config.range,
+            rate: config.rate,
+            allowance: 0.0,
+            extracted: BTreeMap::new(),
+        }
+    }
+
+    pub fn save_state(&self) -> SinkState {
+        SinkState {
+            allowance: self.allowance,
+            extracted: self.extracted.clone(),
+        }
+    }
+
+    pub fn restore_state(&mut self, state: SinkState) {
+        self.allowance = state.allowance;
+        self.extracted = state.extracted;
+    }
+
+    pub fn get_extracted(&self) -> &BTreeMap<String, Extraction> {
+        &self.extracted
+    }
+
+    pub fn captures(&self, position: Vec3) -> bool {
+        (position - self.position)
+            .abs()
+            .cmple(self.range / 2.0)
+            .all()
+    }
+
+    // Extraction capacity that is not used within a step is lost, only the
+    // fraction of a particle carries over.
+    pub fn refill(&mut self, time_step: f32) {
+        if let Some(rate) = self.rate {
+            self.allowance = self.allowance.min(1.0) + rate * time_step;
+        }
+    }
+
+    pub fn extract(&mut self, particle: &SimulationParticle) -> bool {
+        if self.rate.is_some() {
+            if self.allowance < 1.0 {
+                return false;
+            }
+
+            self.allowance -= 1.0;
+        }
+
+        let extraction = self
+            .extracted
+            .entry(particle.get_material_name().clone())
+            .or_default();
+        extraction.count += 1;
+        extraction.mass += particle.get_mass();
+
+        log::debug!(
+            "Sink {} extracted {} particles ({} kg) of {}",
+            self.label,
+            extraction.count,
+            extraction.mass,
+            particle.get_material_name()
         );
+
+        true
+    }
+}
+
+#[derive(Debug)]
+pub struct Door {
+    open: bool,
+    schedule: Vec<DoorEvent>,
+    next_event: usize,
+}
+
+impl Door {
+    pub fn new(config: &DoorConfig) -> Self {
+        let mut schedule = config.schedule.clone();
+        schedule.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        Self {
+            open: config.open,
+            schedule,
+            next_event: 0,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    // Events up to `time` count as applied, so a schedule from a different
+    // config only acts from the restored time on.
+    pub fn restore_state(&mut self, open: bool, time: f32) {
+        self.open = open;
+        self.next_event = self
+            .schedule
+            .iter()
+            .take_while(|event| event.time <= time)
+            .count();
+    }
+
+    // Applies every scheduled event up to `time` and returns whether the door
+    // ended up in a different state.
+    pub fn update(&mut self, time: f32) -> bool {
+        let was_open = self.open;
+
+        while let Some(event) = self.schedule.get(self.next_event) {
+            if event.time > time {
+                break;
+            }
+
+            self.open = event.open;
+            self.next_event += 1;
+        }
+
+        self.open != was_open
     }
 }
 
+// Devices are matched by label on restore, so the state can be loaded into a
+// map built from a modified config.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorldState {
+    actuators: BTreeMap<char, ActuatorState>,
+    sinks: BTreeMap<char, SinkState>,
+    doors: BTreeMap<char, bool>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct DeviceConfig {
     actuators: Vec<ActuatorConfig>,
@@ -132,7 +493,13 @@ pub enum Tile {
     Empty,
     Wall,
     Floor,
+    Heater,
+    Window,
+    Inlet,
+    Outlet,
     User,
+    Door(char),
+    Vent(char),
     Device(char),
 }
 
@@ -142,6 +509,10 @@ impl Tile {
             ' ' => Ok(Tile::Empty),
             '#' => Ok(Tile::Wall),
             '.' => Ok(Tile::Floor),
+            '~' => Ok(Tile::Heater),
+            '=' => Ok(Tile::Window),
+            '+' => Ok(Tile::Inlet),
+            '-' => Ok(Tile::Outlet),
             '@' => Ok(Tile::User),
             _ => Ok(Tile::Device(c)),
         }
@@ -151,24 +522,40 @@ impl Tile {
 #[derive(Debug)]
 pub struct WorldMap {
     tiles: Vec<Vec<Tile>>,
-    actuators: HashMap<char, Actuator>,
+    tile_size: f32,
+    ceiling_height: f32,
+    actuators: BTreeMap<char, Actuator>,
     sensors: HashMap<char, Sensor>,
+    sinks: BTreeMap<char, Sink>,
+    doors: HashMap<char, Door>,
+    vents: HashMap<char, VentConfig>,
 }
 
 impl WorldMap {
     pub fn new(config: &Config) -> Self {
-        let tiles: Vec<Vec<Tile>> = config
-            .get_environment()
+        let environment = config.get_environment();
+        let tile_size = environment.tile_size;
+        let tiles: Vec<Vec<Tile>> = environment
+            .map
             .lines()
             .map(|line| {
                 line.chars()
-                    .map(|c| Tile::from(c).expect("Invalid character"))
+                    .map(|c| match Tile::from(c).expect("Invalid character") {
+                        Tile::Device(c) if config.get_door_by_label(&c).is_some() => Tile::Door(c),
+                        Tile::Device(c) if config.get_vent_by_label(&c).is_some() => Tile::Vent(c),
+                        tile => tile,
+                    })
                     .collect()
             })
             .collect();
 
-        let mut actuators = HashMap::new();
+        let mut actuators = BTreeMap::new();
         let mut sensors = HashMap::new();
+        let mut sinks = BTreeMap::new();
+        let mut doors = HashMap::new();
+        let mut vents = HashMap::new();
+        let scalar_names = config.get_scalar_names();
+        let ambient_temperature = config.get_simulation_config().ambient.temperature;
 
         tiles
             .iter()
@@ -179,86 +566,246 @@ impl WorldMap {
                     .map(move |(x, tile)| (x as f32, z as f32, tile))
             })
             .for_each(|(x, z, tile)| match tile {
+                Tile::Door(c) => {
+                    if let Some(config) = config.get_door_by_label(c) {
+                        doors.entry(*c).or_insert_with(|| Door::new(config));
+                    }
+                }
+                Tile::Vent(c) => {
+                    if let Some(config) = config.get_vent_by_label(c) {
+                        vents.insert(*c, *config);
+                    }
+                }
                 Tile::Device(c) => {
                     match config.get_actuator_by_label(c) {
-                        Some(config) => {
-                            actuators.insert(*c, Actuator::new(x + 0.5, z + 0.5, config));
+                        Some(actuator) => {
+                            let (x, z) = ((x + 0.5) * tile_size, (z + 0.5) * tile_size);
+                            let material = config.get_material(actuator.material.as_ref());
+                            let material_name =
+                                config.get_material_name(actuator.material.as_ref());
+                            let concentrations = config.get_concentrations(&actuator.scalars);
+                            actuators.insert(
+                                *c,
+                                Actuator::new(
+                                    x,
+                                    z,
+                                    actuator,
+                                    material,
+                                    concentrations,
+                                    ambient_temperature,
+                                    config.get_actuator_rng(c),
+                                )
+                                .with_material_name(material_name)
+                                .with_timeline(
+                                    config.get_timeline().get(c).unwrap_or(&Default::default()),
+                                ),
+                            );
                         }
                         None => {}
                     }
 
                     match config.get_sensor_by_label(c) {
                         Some(config) => {
-                            sensors.insert(*c, Sensor::new(*c, x, z, config));
+                            let (x, z) = (x * tile_size, z * tile_size);
+                            let scalars = scalar_names.clone();
+                            sensors.insert(*c, Sensor::new(*c, x, z, config, scalars));
                         }
                         None => {}
                     }
+
+                    if let Some(config) = config.get_sink_by_label(c) {
+                        let (x, z) = ((x + 0.5) * tile_size, (z + 0.5) * tile_size);
+                        sinks.insert(*c, Sink::new(*c, x, z, config));
+                    }
                 }
                 _ => {}
             });
 
+        for label in config.get_timeline().keys() {
+            if !actuators.contains_key(label) {
+                log::warn!("Timeline for unknown actuator {}", label);
+            }
+        }
+
         Self {
             tiles,
+            tile_size,
+            ceiling_height: environment.ceiling_height,
             actuators,
             sensors,
+            sinks,
+            doors,
+            vents,
         }
     }
 
     pub fn build_scene(&mut self, renderer: &Renderer, pipeline: &wgpu::RenderPipeline) -> Scene {
+        let (user_position, floor_instances, wall_instances) = self.scene_instances();
+
+        Scene::new(
+            renderer,
+            pipeline,
+            user_position,
+            floor_instances,
+            wall_instances,
+        )
+    }
+
+    pub fn update_scene(&self, renderer: &Renderer, scene: &mut Scene) {
+        let (_, floor_instances, wall_instances) = self.scene_instances();
+        scene.update_instances(renderer, &floor_instances, &wall_instances);
+    }
+
+    fn scene_instances(&self) -> ((f32, f32), Vec<InstanceVertex>, Vec<InstanceVertex>) {
         let mut user_position = (0.0, 0.0);
         let mut floor_instances = Vec::new();
         let mut wall_instances = Vec::new();
 
         self.iter_tiles().for_each(|(x, z, tile)| match tile {
             Tile::Empty => {}
-            Tile::Wall => {
-                let instance = Self::create_wall_instance(x, z);
+            Tile::Wall | Tile::Window | Tile::Inlet | Tile::Outlet | Tile::Vent(_) => {
+                let instance = self.create_wall_instance(x, z);
                 wall_instances.push(instance);
             }
             Tile::User => {
-                user_position = (x, z);
-                let instance = Self::create_floor_instance(x, z);
+                user_position = (x * self.tile_size, z * self.tile_size);
+                let instance = self.create_floor_instance(x, z);
                 floor_instances.push(instance);
             }
-            Tile::Floor | Tile::Device(_) => {
-                let instance = Self::create_floor_instance(x, z);
+            Tile::Floor | Tile::Heater | Tile::Door(_) | Tile::Device(_) => {
+                let instance = self.create_floor_instance(x, z);
                 floor_instances.push(instance);
             }
         });
 
-        Scene::new(
-            renderer,
-            pipeline,
-            user_position,
-            floor_instances,
-            wall_instances,
-        )
+        (user_position, floor_instances, wall_instances)
     }
 
-    fn iter_tiles(&self) -> impl Iterator<Item = (f32, f32, &Tile)> {
-        self.tiles.iter().enumerate().flat_map(|(z, row)| {
+    pub fn iter_tiles(&self) -> impl Iterator<Item = (f32, f32, &Tile)> {
+        self.tiles.iter().enumerate().flat_map(move |(z, row)| {
             row.iter()
                 .enumerate()
-                .map(move |(x, tile)| (x as f32, z as f32, tile))
+                .map(move |(x, tile)| (x as f32, z as f32, self.resolve(tile)))
         })
     }
 
-    pub fn get_actuators(&mut self) -> &mut HashMap<char, Actuator> {
+    // Closed doors are walls and open doors are floor.
+    fn resolve<'a>(&self, tile: &'a Tile) -> &'a Tile {
+        match tile {
+            Tile::Door(label) => match self.doors.get(label).is_some_and(Door::is_open) {
+                true => &Tile::Floor,
+                false => &Tile::Wall,
+            },
+            _ => tile,
+        }
+    }
+
+    // Advances the door schedules and returns whether any door opened or
+    // closed, in which case the boundaries and the scene are outdated.
+    pub fn update_doors(&mut self, time: f32) -> bool {
+        let mut changed = false;
+
+        for (label, door) in self.doors.iter_mut() {
+            if door.update(time) {
+                let state = if door.is_open() { "opened" } else { "closed" };
+                log::info!("Door {} {} at {}s", label, state, time);
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    pub fn update_timeline(&mut self, time: f32) {
+        for actuator in self.actuators.values_mut() {
+            actuator.update(time);
+        }
+    }
+
+    pub fn get_vent_by_label(&self, label: &char) -> Option<&VentConfig> {
+        self.vents.get(label)
+    }
+
+    pub fn get_tile_size(&self) -> f32 {
+        self.tile_size
+    }
+
+    pub fn get_ceiling_height(&self) -> f32 {
+        self.ceiling_height
+    }
+
+    pub fn get_actuators(&mut self) -> &mut BTreeMap<char, Actuator> {
         &mut self.actuators
     }
 
+    pub fn save_state(&self) -> WorldState {
+        WorldState {
+            actuators: self
+                .actuators
+                .iter()
+                .map(|(label, actuator)| (*label, actuator.save_state()))
+                .collect(),
+            sinks: self
+                .sinks
+                .iter()
+                .map(|(label, sink)| (*label, sink.save_state()))
+                .collect(),
+            doors: self
+                .doors
+                .iter()
+                .map(|(label, door)| (*label, door.is_open()))
+                .collect(),
+        }
+    }
+
+    pub fn restore_state(&mut self, state: WorldState, time: f32) {
+        for (label, state) in state.actuators {
+            match self.actuators.get_mut(&label) {
+                Some(actuator) => actuator.restore_state(state),
+                None => log::warn!("Ignoring state of unknown actuator {}", label),
+            }
+        }
+
+        for (label, state) in state.sinks {
+            match self.sinks.get_mut(&label) {
+                Some(sink) => sink.restore_state(state),
+                None => log::warn!("Ignoring state of unknown sink {}", label),
+            }
+        }
+
+        for (label, open) in state.doors {
+            match self.doors.get_mut(&label) {
+                Some(door) => door.restore_state(open, time),
+                None => log::warn!("Ignoring state of unknown door {}", label),
+            }
+        }
+    }
+
+    pub fn get_sinks(&mut self) -> &mut BTreeMap<char, Sink> {
+        &mut self.sinks
+    }
+
+    pub fn iter_sinks(&self) -> impl Iterator<Item = (&char, &Sink)> {
+        self.sinks.iter()
+    }
+
     pub fn get_sensor_by_label(&self, label: &char) -> Option<&Sensor> {
         self.sensors.get(label)
     }
 
     pub fn get_tile_in_position(&self, position: Vec3) -> &Tile {
-        let (x, z) = ((position.x) as usize, (position.z) as usize);
+        let (x, z) = (
+            (position.x / self.tile_size) as usize,
+            (position.z / self.tile_size) as usize,
+        );
 
-        if z <= self.tiles.len() && x <= self.tiles[z].len() {
-            let tile = &self.tiles[z][x];
+        if z < self.tiles.len() && x < self.tiles[z].len() {
+            let tile = self.resolve(&self.tiles[z][x]);
 
             return match tile {
                 Tile::User => &Tile::Floor,
+                Tile::Heater => &Tile::Floor,
+                Tile::Window | Tile::Inlet | Tile::Outlet | Tile::Vent(_) => &Tile::Wall,
                 Tile::Device(_) => &Tile::Floor,
                 _ => tile,
             };
@@ -268,10 +815,13 @@ impl WorldMap {
     }
 
     pub fn get_device_in_position(&self, position: Vec3) -> Option<char> {
-        let (x, z) = ((position.x) as usize, (position.z) as usize);
+        let (x, z) = (
+            (position.x / self.tile_size) as usize,
+            (position.z / self.tile_size) as usize,
+        );
 
-        if z <= self.tiles.len() {
-            if x <= self.tiles[z].len() {
+        if z < self.tiles.len() {
+            if x < self.tiles[z].len() {
                 let tile = &self.tiles[z][x];
 
                 return match tile {
@@ -284,21 +834,21 @@ impl WorldMap {
         return None;
     }
 
-    fn create_floor_instance(x: f32, z: f32) -> InstanceVertex {
+    fn create_floor_instance(&self, x: f32, z: f32) -> InstanceVertex {
         let transform = Transform::new(
-            Vec3::ONE,
+            Vec3::splat(self.tile_size),
             Quat::from_euler(EulerRot::XYZ, -90.0f32.to_radians(), 0.0, 0.0),
-            Vec3::new(x as f32, 0.0, z as f32 + 1.0),
+            Vec3::new(x * self.tile_size, 0.0, (z + 1.0) * self.tile_size),
         );
 
         InstanceVertex::from_transform(transform)
     }
 
-    fn create_wall_instance(x: f32, z: f32) -> InstanceVertex {
+    fn create_wall_instance(&self, x: f32, z: f32) -> InstanceVertex {
         let transform = Transform::new(
-            Vec3::new(1.0, 3.0, 1.0),
+            Vec3::new(self.tile_size, self.ceiling_height, self.tile_size),
             Quat::IDENTITY,
-            Vec3::new(x as f32, 0.0, z as f32),
+            Vec3::new(x * self.tile_size, 0.0, z * self.tile_size),
         );
 
         InstanceVertex::from_transform(transform)